@@ -0,0 +1,14 @@
+int main() {
+    int arr[5];
+    arr[0] = 3;
+    arr[1] = 4;
+    printf("arr[0] + arr[1] = %d\n", arr[0] + arr[1]);
+
+    int i = 0;
+    while (i < 5) {
+        arr[i] = i * i;
+        i += 1;
+    }
+
+    printf("arr[4] = %d\n", arr[4]);
+}
@@ -0,0 +1,20 @@
+int is_even(int n) {
+    if (n == 0) {
+        return 1;
+    }
+
+    return is_odd(n - 1);
+}
+
+int is_odd(int n) {
+    if (n == 0) {
+        return 0;
+    }
+
+    return is_even(n - 1);
+}
+
+int main() {
+    printf("7 is odd: %d\n", is_odd(7));
+    printf("10 is even: %d\n", is_even(10));
+}
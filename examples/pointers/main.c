@@ -0,0 +1,15 @@
+int set(int* out, int value) {
+    *out = value;
+    return 0;
+}
+
+int main() {
+    int x = 5;
+    int* p = &x;
+
+    *p = 42;
+    printf("x = %d, *p = %d\n", x, *p);
+
+    set(p, x * 2);
+    printf("x = %d\n", x);
+}
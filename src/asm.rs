@@ -0,0 +1,234 @@
+/*
+    Assembly dialect emitted by the code generator.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Att,
+    Intel,
+}
+
+/*
+    Operand size of an instruction.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Width {
+    Byte,
+    Long,
+    Quad,
+}
+
+/*
+    General purpose registers, the name used depends on the access width.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rsi,
+    Rdi,
+    Rbp,
+    Rsp,
+    R8,
+    R9,
+}
+
+/*
+    Two operand arithmetic/logic instructions.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Imul,
+    Cmp,
+    And,
+    Or,
+}
+
+/* Integer argument registers in System V order. */
+pub const ARG_REGS: [Reg; 6] = [Reg::Rdi, Reg::Rsi, Reg::Rdx, Reg::Rcx, Reg::R8, Reg::R9];
+
+/* Doubles are passed in %xmm0-%xmm7. */
+pub const SSE_ARG_REGS: usize = 8;
+
+/*
+    Where an argument is passed. Integer and SSE registers are handed out independently,
+    whatever doesn't fit goes on the stack in argument order.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgSlot {
+    Int(usize),     /* index into ARG_REGS */
+    Sse(usize),     /* %xmm register number */
+    Stack(usize),   /* 8 byte slot, the first one at 0(%rsp) when the call happens */
+}
+
+/*
+    Assigns a slot to every argument, `doubles` says which of them are doubles.
+*/
+pub fn classify_args(doubles: &[bool]) -> Vec<ArgSlot> {
+    let (mut ints, mut sse, mut stack) = (0, 0, 0);
+
+    doubles.iter()
+        .map(|&double| {
+            if double && sse < SSE_ARG_REGS {
+                sse += 1;
+                ArgSlot::Sse(sse - 1)
+            } else if !double && ints < ARG_REGS.len() {
+                ints += 1;
+                ArgSlot::Int(ints - 1)
+            } else {
+                stack += 1;
+                ArgSlot::Stack(stack - 1)
+            }
+        })
+        .collect()
+}
+
+/*
+    A single instruction operand, formatted differently depending on the syntax.
+*/
+#[derive(Debug, Clone)]
+pub enum Operand {
+    /* Immediate value, $5 / 5 */
+    Imm(i64),
+    /* Register accessed at the given width, %eax / eax */
+    Reg(Reg, Width),
+    /* SSE register by number, %xmm0 / xmm0 */
+    Xmm(usize),
+    /* Stack slot relative to %rbp, -8(%rbp) / [rbp-8] */
+    Stack(i64),
+    /* Symbol addressed relative to %rip, .LC0(%rip) / [rip+.LC0] */
+    Rip(String),
+    /* Memory a register points to, (%rax) / [rax] */
+    Mem(Reg),
+    /* Base register plus scaled index register, (%rcx,%rax,4) / [rcx+rax*4] */
+    Index(Reg, Reg, usize),
+    /* Plain label used as a jump or call target */
+    Label(String),
+}
+
+impl Syntax {
+    pub fn parse(name: &str) -> Result<Syntax, String> {
+        match name {
+            "att" => Ok(Syntax::Att),
+            "intel" => Ok(Syntax::Intel),
+            _ => Err(format!("unknown assembly syntax '{}', expected 'att' or 'intel'", name)),
+        }
+    }
+
+    /*
+        Directive placed at the top of the file, if the syntax needs one.
+    */
+    pub fn directive(&self) -> Option<&'static str> {
+        match self {
+            Syntax::Att => None,
+            Syntax::Intel => Some(".intel_syntax noprefix"),
+        }
+    }
+
+    /*
+        Formats a whole instruction.
+        `att` and `intel` are the mnemonics for each dialect, operands are given in AT&T
+        order (source first) and reversed for Intel. `width` adds a size keyword to
+        memory operands in Intel syntax, where the mnemonic carries no size suffix.
+    */
+    pub fn instruction(&self, att: &str, intel: &str, width: Option<Width>, operands: &[Operand]) -> String {
+        let (mnemonic, operands): (&str, Vec<String>) = match self {
+            Syntax::Att => (att, operands.iter().map(|o| self.operand(o, None)).collect()),
+            Syntax::Intel => (intel, operands.iter().rev().map(|o| self.operand(o, width)).collect()),
+        };
+
+        if operands.is_empty() {
+            format!("    {}", mnemonic)
+        } else {
+            format!("    {} {}", mnemonic, operands.join(", "))
+        }
+    }
+
+    fn operand(&self, operand: &Operand, width: Option<Width>) -> String {
+        match (self, operand) {
+            (Syntax::Att, Operand::Imm(n)) => format!("${}", n),
+            (Syntax::Att, Operand::Reg(r, w)) => format!("%{}", r.name(*w)),
+            (Syntax::Att, Operand::Xmm(n)) => format!("%xmm{}", n),
+            (Syntax::Att, Operand::Stack(offset)) => format!("{}(%rbp)", offset),
+            (Syntax::Att, Operand::Rip(sym)) => format!("{}(%rip)", sym),
+            (Syntax::Att, Operand::Mem(reg)) => format!("(%{})", reg.name(Width::Quad)),
+            (Syntax::Att, Operand::Index(base, index, scale)) => {
+                format!("(%{},%{},{})", base.name(Width::Quad), index.name(Width::Quad), scale)
+            }
+
+            (Syntax::Intel, Operand::Imm(n)) => n.to_string(),
+            (Syntax::Intel, Operand::Reg(r, w)) => r.name(*w).to_string(),
+            (Syntax::Intel, Operand::Xmm(n)) => format!("xmm{}", n),
+            (Syntax::Intel, Operand::Stack(offset)) => {
+                let sign = if *offset < 0 { '-' } else { '+' };
+                format!("{}[rbp{}{}]", Self::size_keyword(width), sign, offset.abs())
+            }
+            (Syntax::Intel, Operand::Rip(sym)) => format!("{}[rip+{}]", Self::size_keyword(width), sym),
+            (Syntax::Intel, Operand::Mem(reg)) => format!("{}[{}]", Self::size_keyword(width), reg.name(Width::Quad)),
+            (Syntax::Intel, Operand::Index(base, index, scale)) => {
+                format!("{}[{}+{}*{}]", Self::size_keyword(width), base.name(Width::Quad), index.name(Width::Quad), scale)
+            }
+
+            (_, Operand::Label(label)) => label.clone(),
+        }
+    }
+
+    fn size_keyword(width: Option<Width>) -> &'static str {
+        match width {
+            Some(Width::Byte) => "BYTE PTR ",
+            Some(Width::Long) => "DWORD PTR ",
+            Some(Width::Quad) => "QWORD PTR ",
+            None => "",
+        }
+    }
+}
+
+impl Width {
+    /*
+        AT&T mnemonic suffix for this width.
+    */
+    pub fn suffix(&self) -> char {
+        match self {
+            Width::Byte => 'b',
+            Width::Long => 'l',
+            Width::Quad => 'q',
+        }
+    }
+}
+
+impl Reg {
+    pub fn name(&self, width: Width) -> &'static str {
+        let names = match self {
+            Reg::Rax => ["al", "eax", "rax"],
+            Reg::Rcx => ["cl", "ecx", "rcx"],
+            Reg::Rdx => ["dl", "edx", "rdx"],
+            Reg::Rsi => ["sil", "esi", "rsi"],
+            Reg::Rdi => ["dil", "edi", "rdi"],
+            Reg::Rbp => ["bpl", "ebp", "rbp"],
+            Reg::Rsp => ["spl", "esp", "rsp"],
+            Reg::R8 => ["r8b", "r8d", "r8"],
+            Reg::R9 => ["r9b", "r9d", "r9"],
+        };
+
+        match width {
+            Width::Byte => names[0],
+            Width::Long => names[1],
+            Width::Quad => names[2],
+        }
+    }
+}
+
+impl AluOp {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            AluOp::Add => "add",
+            AluOp::Sub => "sub",
+            AluOp::Imul => "imul",
+            AluOp::Cmp => "cmp",
+            AluOp::And => "and",
+            AluOp::Or => "or",
+        }
+    }
+}
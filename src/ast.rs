@@ -1,8 +1,14 @@
 #[derive(Debug, Clone)]
 pub enum Expr {
-    Identifier(String),
-    Number(f64),
+    Identifier {
+        name: String,
+        line: usize,    /* where the reference appears, for diagnostics */
+        column: usize,
+    },
+    Int(i64),
+    Float(f64),
     String(String),
+    Char(u8),
     BinaryOp {
         left: Box<Expr>,
         op: Binop,
@@ -11,7 +17,28 @@ pub enum Expr {
     FunctionCall {
         callee: String,
         args: Vec<Expr>
-    }
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    /* Value a pointer points to, *p */
+    Deref(Box<Expr>),
+    /* Address of a variable or array element, &x */
+    AddrOf(Box<Expr>),
+    /* (a, b), evaluates every expression in order and yields the last one */
+    Comma(Vec<Expr>),
+    /* Array element, arr[i] */
+    Index {
+        base: Box<Expr>,
+        index: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum UnaryOp {
+    Neg,    /* -x */
+    Not,    /* !x */
 }
 
 #[derive(Debug, Clone)]
@@ -20,14 +47,49 @@ pub enum Binop {
     Sub,
     Mul,
     Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    And,
+    Or,
 }
 
 #[derive(Debug)]
 pub enum Stmt {
-    Expression(Expr),
+    Expression(Expr, usize),    /* expression, line */
     VariableDecl(VariableDecl), /* name, value */
+    MultiDecl(Vec<VariableDecl>), /* int a = 1, b = 2; declared in order */
+    ArrayDecl(ArrayDecl),
     FunctionDecl(FunctionDecl),
     Return(Return),
+    ExternDecl(ExternDecl),
+    If(If),
+    While(While),
+    Assign(Assign),
+}
+
+impl Stmt {
+    /*
+        Source line the statement starts on.
+    */
+    pub fn line(&self) -> usize {
+        match self {
+            Stmt::Expression(_, line) => *line,
+            Stmt::VariableDecl(vdecl) => vdecl.line,
+            Stmt::MultiDecl(vdecls) => vdecls.first().map_or(0, |vdecl| vdecl.line),
+            Stmt::ArrayDecl(adecl) => adecl.line,
+            Stmt::FunctionDecl(fdecl) => fdecl.line,
+            Stmt::Return(ret) => ret.line,
+            Stmt::ExternDecl(decl) => decl.line,
+            Stmt::If(if_stmt) => if_stmt.line,
+            Stmt::While(while_stmt) => while_stmt.line,
+            Stmt::Assign(assign) => assign.line,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -35,17 +97,84 @@ pub struct VariableDecl {
     pub data_type: String,
     pub name: String,
     pub value: Expr,
+    pub line: usize,
+    /*
+        Declared with `register`, a hint to keep the variable out of memory, so its
+        address can't be taken. There's no register allocator yet so it still gets a
+        stack slot.
+    */
+    pub register: bool,
+}
+
+/*
+    A fixed size local array, e.g. `int arr[5];`, the elements start out uninitialized.
+*/
+#[derive(Debug)]
+pub struct ArrayDecl {
+    pub data_type: String,  /* of the elements */
+    pub name: String,
+    pub size: usize,
+    pub line: usize,
+}
+
+/*
+    A global variable defined in another object, e.g. `extern int counter;`
+*/
+#[derive(Debug)]
+pub struct ExternDecl {
+    pub data_type: String,
+    pub name: String,
+    pub line: usize,
+    pub params: Option<Vec<Parameter>>,    /* a function prototype, extern double sin(double x); */
 }
 
 #[derive(Debug)]
 pub struct Return {
     pub value: Expr,
+    pub line: usize,
+}
+
+/*
+    if (cond) { ... } else { ... }, an `else if` is an If nested in the else body.
+*/
+#[derive(Debug)]
+pub struct If {
+    pub cond: Expr,
+    pub then_body: Vec<Stmt>,
+    pub else_body: Option<Vec<Stmt>>,
+    pub line: usize,
+}
+
+/*
+    Assignment to an already declared variable, e.g. `x = x + 1;`, to an element
+    of an array, e.g. `arr[i] = 0;`, or through a pointer, e.g. `*p = 0;`
+*/
+#[derive(Debug)]
+pub struct Assign {
+    pub name: String,
+    pub index: Option<Expr>,
+    pub deref: bool,
+    pub value: Expr,
+    pub line: usize,
+}
+
+#[derive(Debug)]
+pub struct While {
+    pub cond: Expr,
+    pub body: Vec<Stmt>,
+    pub line: usize,
 }
 
 #[derive(Debug)]
 pub struct Parameter {
     pub data_type: String,
     pub name: String,
+    /*
+        Pointer parameter declared with `restrict`, the caller promises nothing else
+        aliases the pointee. Not used by codegen yet, but allows loads and stores
+        through it to be reordered once there's an optimizer.
+    */
+    pub restrict: bool,
 }
 
 #[derive(Debug)]
@@ -54,6 +183,31 @@ pub struct FunctionDecl {
     pub name: String,
     pub body: Vec<Stmt>,
     pub params: Vec<Parameter>,
+    pub is_static: bool,    /* file-local, the label isn't exported */
+    pub attributes: Vec<Attribute>,
+    pub line: usize,
+    pub file: String,   /* named by a #line marker, empty if there was none */
+}
+
+/*
+    Function metadata written before the declaration, e.g. `@noreturn int fail() { ... }`
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attribute {
+    Inline,     /* hint that the body is worth inlining */
+    NoReturn,   /* calls never come back to the caller */
+    Cold,       /* rarely executed, kept away from the hot code */
+}
+
+impl Attribute {
+    pub fn parse(name: &str) -> Option<Attribute> {
+        match name {
+            "inline" => Some(Attribute::Inline),
+            "noreturn" => Some(Attribute::NoReturn),
+            "cold" => Some(Attribute::Cold),
+            _ => None,
+        }
+    }
 }
 
 impl Binop {
@@ -62,8 +216,12 @@ impl Binop {
     */
     pub fn precedence(&self) -> u8 {
         match self {
-            Binop::Add | Binop::Sub => 1,
-            Binop::Mul | Binop::Div => 2,
+            Binop::Or => 1,
+            Binop::And => 2,
+            Binop::Eq | Binop::Ne => 3,
+            Binop::Lt | Binop::Gt | Binop::Le | Binop::Ge => 4,
+            Binop::Add | Binop::Sub => 5,
+            Binop::Mul | Binop::Div | Binop::Mod => 6,
         }
     }
 
@@ -74,4 +232,8 @@ impl Binop {
     pub fn is_left_linked(&self) -> bool {
         true
     }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, Binop::Eq | Binop::Ne | Binop::Lt | Binop::Gt | Binop::Le | Binop::Ge)
+    }
 }
@@ -1,15 +1,71 @@
 use std::collections::HashMap;
-use std::fmt::format;
-use crate::ast::{Binop, Expr, FunctionDecl, Parameter, Return, Stmt, VariableDecl};
+use crate::asm::{classify_args, AluOp, ArgSlot, Operand, Reg, Syntax, Width, ARG_REGS};
+use crate::ast::{ArrayDecl, Assign, Attribute, Binop, Expr, ExternDecl, FunctionDecl, If, Parameter, Return, Stmt, UnaryOp, VariableDecl, While};
+use crate::error::CrustyError;
+
+/*
+    Return types of common C library functions that don't return int, so calls to them
+    are typed without a prototype. Anything else undeclared is assumed to return int.
+*/
+const LIBRARY_RETURN_TYPES: [(&str, &str); 30] = [
+    ("malloc", "char*"), ("calloc", "char*"), ("realloc", "char*"),
+    ("strcpy", "char*"), ("strncpy", "char*"), ("strcat", "char*"), ("strchr", "char*"),
+    ("strrchr", "char*"), ("strstr", "char*"), ("strdup", "char*"), ("getenv", "char*"),
+    ("memcpy", "char*"), ("memmove", "char*"), ("memset", "char*"),
+    ("strlen", "long"), ("atol", "long"), ("strtol", "long"), ("labs", "long"), ("time", "long"),
+    ("sqrt", "double"), ("pow", "double"), ("fabs", "double"), ("sin", "double"), ("cos", "double"),
+    ("tan", "double"), ("exp", "double"), ("log", "double"), ("floor", "double"), ("ceil", "double"),
+    ("atof", "double"),
+];
+
+/*
+    C library functions returning `int` (or nothing) that can be called without an
+    extern prototype.
+*/
+const LIBRARY_FUNCTIONS: [&str; 28] = [
+    "printf", "sprintf", "snprintf", "fprintf", "scanf", "sscanf", "puts", "putchar", "getchar",
+    "exit", "abort", "free", "abs", "atoi", "rand", "srand", "system", "sleep",
+    "strcmp", "strncmp", "memcmp", "isdigit", "isalpha", "isalnum", "isspace", "isupper",
+    "toupper", "tolower",
+];
+
+pub fn library_return_type(name: &str) -> Option<&'static str> {
+    LIBRARY_RETURN_TYPES.iter().find(|(function, _)| *function == name).map(|(_, return_type)| *return_type)
+}
+
+pub fn is_library_function(name: &str) -> bool {
+    LIBRARY_FUNCTIONS.contains(&name) || library_return_type(name).is_some()
+}
+
+/*
+    What a caller needs to know about a function defined in the file or declared with
+    an extern prototype.
+*/
+struct Signature {
+    return_type: String,
+    param_types: Vec<String>,
+    external: bool,     /* only a prototype, the definition is in another object */
+}
 
 pub struct CodeGen {
     output: String,
     strings: HashMap<String, usize>,
-    variable_offsets: HashMap<String, usize>,
-    variable_types: HashMap<String, String>,
+    doubles: HashMap<u64, usize>,   /* double constants by bit pattern -> label */
+    variable_offsets: Vec<HashMap<String, usize>>,   /* one map per open block, innermost last */
+    variable_types: Vec<HashMap<String, String>>,
+    globals: HashMap<String, String>,   /* extern globals, name -> data type */
+    functions: HashMap<String, Signature>,
     string_sect: String,
     label_count: usize,
     rbp_offset: usize,
+    max_rbp_offset: usize,  /* deepest rbp_offset reached in the function, sizes the frame */
+    stack_depth: usize, /* bytes pushed below the frame, used to keep calls aligned */
+    current_fn: String,
+    current_file: String,   /* of the function being generated, for errors */
+    current_line: usize,    /* of the statement being generated, for errors */
+    frame_sizes: Vec<(String, usize)>,  /* function name -> aligned frame size */
+    syntax: Syntax,
+    debug_file: Option<String>, /* source named in the line table, no .loc directives unless set */
     isize: usize,   /* indent size */
 }
 
@@ -17,21 +73,79 @@ impl CodeGen {
     pub fn new() -> Self {
         Self {
             output: String::new(),
-            variable_offsets: HashMap::new(),
-            variable_types: HashMap::new(),
+            variable_offsets: vec![],
+            variable_types: vec![],
+            globals: HashMap::new(),
+            functions: HashMap::new(),
             string_sect: String::new(),
             strings: HashMap::new(),
+            doubles: HashMap::new(),
             label_count: 0,
             rbp_offset: 0,
+            max_rbp_offset: 0,
+            stack_depth: 0,
+            current_fn: String::new(),
+            current_file: String::new(),
+            current_line: 0,
+            frame_sizes: vec![],
+            syntax: Syntax::Att,
+            debug_file: None,
             isize: 0,
         }
     }
 
-    pub fn generate(&mut self, stmts: &[Stmt]) -> Result<String, String> {
+    pub fn with_syntax(mut self, syntax: Syntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /*
+        Maps the generated code back to lines of `file` with .file/.loc directives,
+        the assembler turns them into a DWARF line table for debuggers.
+    */
+    pub fn with_debug_file(mut self, file: &str) -> Self {
+        self.debug_file = Some(file.to_string());
+        self
+    }
+
+    /*
+        Frame size reserved by each generated function, in declaration order.
+    */
+    pub fn frame_sizes(&self) -> &[(String, usize)] {
+        &self.frame_sizes
+    }
+
+    pub fn generate(&mut self, stmts: &[Stmt]) -> Result<String, CrustyError> {
+        self.generate_program(stmts)
+            .map_err(|msg| CrustyError::Codegen { msg, file: self.current_file.clone(), line: self.current_line })
+    }
+
+    fn generate_program(&mut self, stmts: &[Stmt]) -> Result<String, String> {
         /* collect all string s */
         let mut t_output = String::new();
         std::mem::swap(&mut self.output, &mut t_output);
 
+        /* signatures first, so calls can be checked even before the definition */
+        for stmt in stmts {
+            match stmt {
+                Stmt::FunctionDecl(func_decl) => {
+                    self.functions.insert(func_decl.name.clone(), Signature {
+                        return_type: func_decl.data_type.clone(),
+                        param_types: func_decl.params.iter().map(|param| param.data_type.clone()).collect(),
+                        external: false,
+                    });
+                }
+                Stmt::ExternDecl(ExternDecl { data_type, name, params: Some(params), .. }) => {
+                    self.functions.entry(name.clone()).or_insert(Signature {
+                        return_type: data_type.clone(),
+                        param_types: params.iter().map(|param| param.data_type.clone()).collect(),
+                        external: true,
+                    });
+                }
+                _ => {}
+            }
+        }
+
         for stmt in stmts {
             self.generate_stmt(stmt)?;
         }
@@ -39,192 +153,1080 @@ impl CodeGen {
         let code_sect = self.output.clone();
         self.output = t_output;
 
+        if let Some(directive) = self.syntax.directive() {
+            self.emit_line(directive);
+        }
+
+        if let Some(file) = self.debug_file.clone() {
+            self.emit_line(&format!("    .file 1 \"{}\"", self.get_escaped_string(&file)));
+        }
+
         if !self.string_sect.is_empty() {
             self.emit_line(".section .rodata");
             self.emit(&self.string_sect.clone());
         }
 
         self.emit_line(".section .text");
-        self.emit_line("    .globl main");
-        self.emit_line("    .type main, @function");
         self.emit(&code_sect);
 
+        /* without the note the linker assumes the program needs an executable stack */
+        self.emit_line(".section .note.GNU-stack,\"\",@progbits");
+
         Ok(self.output.clone())
     }
 
     fn generate_stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+        self.current_line = stmt.line();
+
+        /* declarations don't produce code at their own position */
+        if !matches!(stmt, Stmt::FunctionDecl(_) | Stmt::ExternDecl(_)) {
+            self.emit_loc(stmt.line());
+        }
+
         match stmt {
             Stmt::VariableDecl(vdecl) => self.generate_var_decl(vdecl),
+            Stmt::MultiDecl(vdecls) => {
+                for vdecl in vdecls {
+                    self.generate_var_decl(vdecl)?;
+                }
+                Ok(())
+            }
+            Stmt::ArrayDecl(adecl) => self.generate_array_decl(adecl),
             Stmt::FunctionDecl(fdecl) => self.generate_fn_decl(fdecl),
-            Stmt::Expression(expr) => self.generate_expr_stmt(expr),
+            Stmt::Expression(expr, _) => self.generate_expr_stmt(expr),
             Stmt::Return(ret) => self.generate_return_stmt(ret),
+            Stmt::If(if_stmt) => self.generate_if_stmt(if_stmt),
+            Stmt::While(while_stmt) => self.generate_while_stmt(while_stmt),
+            Stmt::Assign(assign) => self.generate_assign(assign),
+            Stmt::ExternDecl(decl) => {
+                /* No storage, the symbol is defined by another object file. Prototypes were collected up front. */
+                if decl.params.is_none() {
+                    self.globals.insert(decl.name.clone(), decl.data_type.clone());
+                }
+                Ok(())
+            }
         }
     }
 
     fn generate_return_stmt(&mut self, ret: &Return) -> Result<(), String> {
+        let return_type = self.functions.get(&self.current_fn).map(|sig| sig.return_type.clone()).unwrap_or_default();
+
         match &ret.value {
-            Expr::Number(n) => { self.emit_line(&format!("    movl ${}, %eax", *n as i32)) },
-            Expr::Identifier(ident) => {
-                let offset = self.get_variable_offset(ident)?;
-                let data_type = self.variable_types.get(ident).ok_or_else(|| format!("tried to get data type for variable {}", ident))?;
+            value if return_type == "long" => self.generate_expr_as(value, Width::Quad)?,
+            /* doubles are returned in %xmm0 */
+            value if return_type == "double" => self.generate_double(value)?,
+            value if self.is_double(value) => self.generate_expr(value)?,
+            Expr::Int(n) => self.mov(Width::Long, Operand::Imm(*n as i32 as i64), Operand::Reg(Reg::Rax, Width::Long)),
+            Expr::Identifier { name, line, column } => {
+                let (location, data_type) = self.get_identifier(name, *line, *column)?;
 
                 match data_type.as_str() {
-                    "int" => self.emit_line(&format!("    movl -{}(%rbp), %eax", offset)),
-                    "char" => self.emit_line(&format!("    movzbl -{}(%rbp), %eax", offset)),
+                    "int" => self.mov(Width::Long, location, Operand::Reg(Reg::Rax, Width::Long)),
+                    "char" | "bool" => self.movzx(location, Reg::Rax),
+                    ty if Self::is_quad(ty) => self.mov(Width::Quad, location, Operand::Reg(Reg::Rax, Width::Quad)),
                     _ => return Err("unable to return this data type".to_string())
                 }
             },
+            value @ (Expr::Char(_) | Expr::String(_) | Expr::BinaryOp { .. } | Expr::Unary { .. } | Expr::FunctionCall { .. } | Expr::Index { .. } | Expr::Deref(_) | Expr::AddrOf(_) | Expr::Comma(_)) => {
+                self.generate_expr(value)?
+            }
 
             _ => return Err("unsupported return expression".to_string())
         }
 
+        /*
+            A return can appear anywhere in the body, so jump to the function's
+            shared epilogue instead of falling through to the next statement.
+        */
+        self.jmp(&format!(".Lreturn_{}", self.current_fn));
+        Ok(())
+    }
+
+    /*
+        Stores the value into the variable's existing slot, at the width of its type.
+    */
+    fn generate_assign(&mut self, assign: &Assign) -> Result<(), String> {
+        if let Some(index) = &assign.index {
+            return self.generate_element_assign(assign, index);
+        }
+
+        if assign.deref {
+            return self.generate_deref_assign(assign);
+        }
+
+        let (location, data_type) = self.get_variable(&assign.name)?;
+        if data_type == "double" {
+            self.generate_double(&assign.value)?;
+            self.movsd(Operand::Xmm(0), location);
+            return Ok(());
+        }
+
+        if data_type == "long" {
+            self.generate_expr_as(&assign.value, Width::Quad)?;
+        } else {
+            self.generate_expr(&assign.value)?;
+        }
+
+        match data_type.as_str() {
+            "int" => self.mov(Width::Long, Operand::Reg(Reg::Rax, Width::Long), location),
+            "char" => self.mov(Width::Byte, Operand::Reg(Reg::Rax, Width::Byte), location),
+            ty if Self::is_quad(ty) => self.mov(Width::Quad, Operand::Reg(Reg::Rax, Width::Quad), location),
+            "bool" => self.store_bool(location),
+            _ => return Err(format!("unable to assign to a variable of type: {}", data_type)),
+        }
+
+        Ok(())
+    }
+
+    /*
+        Stores %eax into a bool, any nonzero value becomes 1 like a conversion in C.
+    */
+    fn store_bool(&mut self, location: Operand) {
+        self.bin_op(AluOp::Cmp, Width::Long, Operand::Imm(0), Operand::Reg(Reg::Rax, Width::Long));
+        self.inst("setne", None, &[Operand::Reg(Reg::Rax, Width::Byte)]);
+        self.mov(Width::Byte, Operand::Reg(Reg::Rax, Width::Byte), location);
+    }
+
+    fn generate_if_stmt(&mut self, if_stmt: &If) -> Result<(), String> {
+        let id = self.label_count;
+        self.label_count += 1;
+
+        let else_label = format!(".Lelse_{}", id);
+        let end_label = format!(".Lend_if_{}", id);
+
+        self.jump_on_condition(&if_stmt.cond, false, &else_label)?;
+
+        self.generate_block(&if_stmt.then_body)?;
+
+        self.jmp(&end_label);
+        self.emit(&format!("{}:\n", else_label));
+
+        if let Some(else_body) = &if_stmt.else_body {
+            self.generate_block(else_body)?;
+        }
+
+        self.emit(&format!("{}:\n", end_label));
         Ok(())
     }
 
+    fn generate_while_stmt(&mut self, while_stmt: &While) -> Result<(), String> {
+        let id = self.label_count;
+        self.label_count += 1;
+
+        let start_label = format!(".Lwhile_start_{}", id);
+        let end_label = format!(".Lwhile_end_{}", id);
+
+        self.emit(&format!("{}:\n", start_label));
+        self.jump_on_condition(&while_stmt.cond, false, &end_label)?;
+
+        self.generate_block(&while_stmt.body)?;
+
+        self.jmp(&start_label);
+        self.emit(&format!("{}:\n", end_label));
+        Ok(())
+    }
+
+    /*
+        Evaluates a condition and jumps to `label` when its truth value is `truth`.
+        Doubles are compared against 0.0 instead of being truncated, so 0.5 is true,
+        and NaN is true as in C.
+    */
+    fn jump_on_condition(&mut self, cond: &Expr, truth: bool, label: &str) -> Result<(), String> {
+        if !self.is_double(cond) {
+            self.generate_expr(cond)?;
+            let width = self.expr_width(cond);
+            self.bin_op(AluOp::Cmp, width, Operand::Imm(0), Operand::Reg(Reg::Rax, width));
+            self.jump_if(if truth { "jne" } else { "je" }, label);
+            return Ok(());
+        }
+
+        self.generate_double(cond)?;
+        self.inst("xorpd", None, &[Operand::Xmm(1), Operand::Xmm(1)]);
+        self.inst_with("ucomisd", "ucomisd", None, &[Operand::Xmm(1), Operand::Xmm(0)]);
+
+        /* ucomisd sets ZF both for equal and unordered operands, PF only for unordered */
+        if truth {
+            self.jump_if("jne", label);
+            self.jump_if("jp", label);
+        } else {
+            let nan_label = format!(".Lnan_{}", self.label_count);
+            self.label_count += 1;
+
+            self.jump_if("jp", &nan_label);
+            self.jump_if("je", label);
+            self.emit(&format!("{}:\n", nan_label));
+        }
+
+        Ok(())
+    }
+
+    /*
+        Generates the statements of a braced body in a scope of its own. Locals declared
+        inside shadow outer ones with the same name, and their slots are handed back
+        once the block ends so sibling blocks can reuse them.
+    */
+    fn generate_block(&mut self, body: &[Stmt]) -> Result<(), String> {
+        let start_offset = self.rbp_offset;
+        self.push_scope();
+
+        for stmt in body.iter() {
+            self.generate_stmt(stmt)?;
+        }
+
+        self.pop_scope();
+        self.rbp_offset = start_offset;
+        Ok(())
+    }
+
+    fn push_scope(&mut self) {
+        self.variable_offsets.push(HashMap::new());
+        self.variable_types.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.variable_offsets.pop();
+        self.variable_types.pop();
+    }
+
+    /*
+        Binds a name in the innermost scope.
+    */
+    fn declare_variable(&mut self, name: &str, data_type: &str) {
+        self.max_rbp_offset = self.max_rbp_offset.max(self.rbp_offset);
+
+        if let (Some(offsets), Some(types)) = (self.variable_offsets.last_mut(), self.variable_types.last_mut()) {
+            offsets.insert(name.to_string(), self.rbp_offset);
+            types.insert(name.to_string(), data_type.to_string());
+        }
+    }
+
+    /*
+        Reserves size * elem_size bytes, element 0 at the lowest address like in C.
+        The array's offset is that of element 0, element i sits i * elem_size above it.
+    */
+    fn generate_array_decl(&mut self, array: &ArrayDecl) -> Result<(), String> {
+        if self.variable_offsets.last().is_some_and(|scope| scope.contains_key(&array.name)) {
+            return Err(format!("variable '{}' already declared in this scope at line {}", array.name, array.line));
+        }
+
+        if !matches!(array.data_type.as_str(), "int" | "char" | "long" | "char*") {
+            return Err(format!("arrays of '{}' aren't supported yet, at line {}", array.data_type, array.line));
+        }
+
+        self.rbp_offset += self.get_type_size(&array.data_type) * array.size;
+        self.rbp_offset = self.rbp_offset.next_multiple_of(8);
+
+        self.declare_variable(&array.name, &format!("{}[]", array.data_type));
+        Ok(())
+    }
+
+    /*
+        Where an array element lives and its type. A constant index is folded into the
+        %rbp offset, anything else is computed into %rax and scaled off the array's
+        address in %rcx.
+    */
+    fn element_location(&mut self, base: &Expr, index: &Expr) -> Result<(Operand, String), String> {
+        let Expr::Identifier { name, line, column } = base else {
+            return Err("only named arrays can be indexed".to_string());
+        };
+
+        let (location, data_type) = self.get_identifier(name, *line, *column)?;
+        let (Operand::Stack(offset), Some(elem_type)) = (&location, data_type.strip_suffix("[]")) else {
+            return Err(format!("'{}' is not an array at line {}, col {}", name, line, column));
+        };
+
+        let elem_size = self.get_type_size(elem_type);
+        if let Expr::Int(i) = index {
+            return Ok((Operand::Stack(offset + i * elem_size as i64), elem_type.to_string()));
+        }
+
+        self.generate_expr_as(index, Width::Quad)?;
+        self.lea(location.clone(), Reg::Rcx);
+        Ok((Operand::Index(Reg::Rcx, Reg::Rax, elem_size), elem_type.to_string()))
+    }
+
+    fn generate_index(&mut self, base: &Expr, index: &Expr) -> Result<(), String> {
+        let (location, elem_type) = self.element_location(base, index)?;
+
+        match elem_type.as_str() {
+            "int" => self.mov(Width::Long, location, Operand::Reg(Reg::Rax, Width::Long)),
+            "char" => self.movzx(location, Reg::Rax),
+            _ => self.mov(Width::Quad, location, Operand::Reg(Reg::Rax, Width::Quad)),
+        }
+
+        Ok(())
+    }
+
+    /*
+        arr[i] = value, the value is computed first and kept in %rdx while the
+        element's address is worked out.
+    */
+    fn generate_element_assign(&mut self, assign: &Assign, index: &Expr) -> Result<(), String> {
+        let base = Expr::Identifier { name: assign.name.clone(), line: assign.line, column: 0 };
+
+        self.generate_expr_as(&assign.value, Width::Quad)?;
+        self.push(Reg::Rax);
+        let (location, elem_type) = self.element_location(&base, index)?;
+        self.pop(Reg::Rdx);
+
+        let width = match elem_type.as_str() {
+            "int" => Width::Long,
+            "char" => Width::Byte,
+            _ => Width::Quad,
+        };
+
+        self.mov(width, Operand::Reg(Reg::Rdx, width), location);
+        Ok(())
+    }
+
+    /*
+        *p = value, the pointer is only loaded once the value is computed.
+    */
+    fn generate_deref_assign(&mut self, assign: &Assign) -> Result<(), String> {
+        let (location, data_type) = self.get_variable(&assign.name)?;
+        let Some(pointee) = data_type.strip_suffix('*') else {
+            return Err(format!("'{}' is not a pointer at line {}", assign.name, assign.line));
+        };
+
+        if pointee == "double" {
+            self.generate_double(&assign.value)?;
+            self.mov(Width::Quad, location, Operand::Reg(Reg::Rcx, Width::Quad));
+            self.movsd(Operand::Xmm(0), Operand::Mem(Reg::Rcx));
+            return Ok(());
+        }
+
+        self.generate_expr_as(&assign.value, Width::Quad)?;
+        self.mov(Width::Quad, location, Operand::Reg(Reg::Rcx, Width::Quad));
+
+        match pointee {
+            "int" => self.mov(Width::Long, Operand::Reg(Reg::Rax, Width::Long), Operand::Mem(Reg::Rcx)),
+            "char" => self.mov(Width::Byte, Operand::Reg(Reg::Rax, Width::Byte), Operand::Mem(Reg::Rcx)),
+            "bool" => self.store_bool(Operand::Mem(Reg::Rcx)),
+            _ => self.mov(Width::Quad, Operand::Reg(Reg::Rax, Width::Quad), Operand::Mem(Reg::Rcx)),
+        }
+
+        Ok(())
+    }
+
+    /*
+        (a, b, c), everything but the last expression is only evaluated for its side
+        effects, the last one is generated by `last` and gives the value.
+    */
+    fn generate_comma(&mut self, exprs: &[Expr], last: fn(&mut Self, &Expr) -> Result<(), String>) -> Result<(), String> {
+        let (value, effects) = exprs.split_last().ok_or("empty comma expression")?;
+        for expr in effects {
+            self.generate_expr(expr)?;
+        }
+
+        last(self, value)
+    }
+
+    /*
+        &x or &arr[i], the address of the variable's slot.
+    */
+    fn generate_addr_of(&mut self, operand: &Expr) -> Result<(), String> {
+        let location = match operand {
+            Expr::Identifier { name, line, column } => self.get_identifier(name, *line, *column)?.0,
+            Expr::Index { base, index } => self.element_location(base, index)?.0,
+            _ => return Err("can only take the address of a variable or an array element".to_string()),
+        };
+
+        self.lea(location, Reg::Rax);
+        Ok(())
+    }
+
+    /*
+        *p, loads the value p points to at the width of the pointee type.
+    */
+    fn generate_deref(&mut self, pointer: &Expr) -> Result<(), String> {
+        let pointee = self.pointee_type(pointer)
+            .ok_or_else(|| format!("can only dereference a pointer in '{}'", self.current_fn))?;
+
+        self.generate_expr(pointer)?;
+
+        match pointee.as_str() {
+            "int" => self.mov(Width::Long, Operand::Mem(Reg::Rax), Operand::Reg(Reg::Rax, Width::Long)),
+            "char" | "bool" => self.movzx(Operand::Mem(Reg::Rax), Reg::Rax),
+            ty if Self::is_quad(ty) => self.mov(Width::Quad, Operand::Mem(Reg::Rax), Operand::Reg(Reg::Rax, Width::Quad)),
+            _ => return Err(format!("unable to dereference a pointer to '{}'", pointee)),
+        }
+
+        Ok(())
+    }
+
+    /*
+        Declared type of an expression, as far as it can be told without evaluating it.
+        Enough to know what a dereference loads.
+    */
+    fn value_type(&self, expr: &Expr) -> Option<String> {
+        match expr {
+            Expr::Identifier { name, .. } => self.get_variable(name).ok().map(|(_, data_type)| data_type),
+            Expr::String(_) => Some("char*".to_string()),
+            Expr::FunctionCall { callee, .. } => Some(self.return_type(callee)),
+            Expr::AddrOf(operand) => Some(format!("{}*", self.value_type(operand)?)),
+            Expr::Deref(pointer) => self.pointee_type(pointer),
+            Expr::Index { base, .. } => self.value_type(base)?.strip_suffix("[]").map(str::to_string),
+            Expr::BinaryOp { left, op: op @ (Binop::Add | Binop::Sub), right } => {
+                match (self.pointer_type(left), self.pointer_type(right)) {
+                    (Some(_), Some(_)) if matches!(op, Binop::Sub) => Some("long".to_string()),
+                    (Some(pointer), None) | (None, Some(pointer)) => Some(pointer),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn pointer_type(&self, expr: &Expr) -> Option<String> {
+        self.value_type(expr).filter(|data_type| data_type.ends_with('*'))
+    }
+
+    fn pointee_type(&self, pointer: &Expr) -> Option<String> {
+        self.value_type(pointer)?.strip_suffix('*').map(str::to_string)
+    }
+
+    /*
+        Types held in a full 64 bit register, pointers and long.
+    */
+    fn is_quad(data_type: &str) -> bool {
+        data_type == "long" || data_type.ends_with('*')
+    }
+
     fn generate_var_decl(&mut self, var_decl: &VariableDecl) -> Result<(), String> {
+        /* shadowing is only allowed from an inner block */
+        if self.variable_offsets.last().is_some_and(|scope| scope.contains_key(&var_decl.name)) {
+            return Err(format!("variable '{}' already declared in this scope at line {}", var_decl.name, var_decl.line));
+        }
+
         let size_offset = self.get_type_size(&var_decl.data_type);
 
         self.rbp_offset += size_offset;
-        if self.rbp_offset % 8 != 0 {
+        if !self.rbp_offset.is_multiple_of(8) {
             self.rbp_offset += 8 - (self.rbp_offset % 8);
         }
 
-        self.variable_offsets.insert(var_decl.name.clone(), self.rbp_offset);
-        self.variable_types.insert(var_decl.name.clone(), var_decl.data_type.clone());
+        self.declare_variable(&var_decl.name, &var_decl.data_type);
 
         match var_decl.value.clone() {
-            Expr::Number(n) => Ok(self.emit(&format!("    movl ${}, -{}(%rbp)\n", n, self.rbp_offset))),
+            value if var_decl.data_type == "double" => {
+                self.generate_double(&value)?;
+                self.movsd(Operand::Xmm(0), self.slot(self.rbp_offset));
+                Ok(())
+            },
+            /* an imm32 is sign extended by movq, anything wider goes through movabsq */
+            Expr::Int(n) if var_decl.data_type == "long" && Self::fits_imm32(n) => {
+                self.mov(Width::Quad, Operand::Imm(n), self.slot(self.rbp_offset));
+                Ok(())
+            },
+            value if var_decl.data_type == "long" => {
+                self.generate_expr_as(&value, Width::Quad)?;
+                self.mov(Width::Quad, Operand::Reg(Reg::Rax, Width::Quad), self.slot(self.rbp_offset));
+                Ok(())
+            },
+            Expr::Int(n) if var_decl.data_type == "bool" => {
+                self.mov(Width::Byte, Operand::Imm((n != 0) as i64), self.slot(self.rbp_offset));
+                Ok(())
+            },
+            Expr::Int(n) => {
+                self.mov(Width::Long, Operand::Imm(n as i32 as i64), self.slot(self.rbp_offset));
+                Ok(())
+            },
+            Expr::Char(c) => {
+                let width = if var_decl.data_type == "int" { Width::Long } else { Width::Byte };
+                self.mov(width, Operand::Imm(c as i64), self.slot(self.rbp_offset));
+                Ok(())
+            },
             Expr::String(str) => {
-                /* only process chars */
-                if str.len() == 1 {
-                    Ok(self.emit(&format!("    movl ${}, -{}(%rbp)\n", str.as_bytes()[0], self.rbp_offset)))
-                } else {
-                    self.generate_string(&*str)?;
-                    let label = self.strings.get(&str).unwrap();
-                    self.emit(format!("    leaq .LC{}(%rip), %rax\n", label).as_str());
-                    self.emit(format!("    movq %rax, -{}(%rbp)\n", self.rbp_offset).as_str());
-                    Ok(())
-                }
+                self.generate_string(&str)?;
+                let label = self.strings.get(&str).unwrap();
+                self.lea(Operand::Rip(format!(".LC{}", label)), Reg::Rax);
+                self.mov(Width::Quad, Operand::Reg(Reg::Rax, Width::Quad), self.slot(self.rbp_offset));
+                Ok(())
             },
-            Expr::FunctionCall { callee, args } => {
-                self.generate_function_call(&callee, &args)?;
+            /* anything else is evaluated into %eax, a double stored in an integer variable is truncated */
+            value => {
+                self.generate_expr(&value)?;
                 match var_decl.data_type.as_str() {
-                    "int" => self.emit_line(&format!("    movl %eax, -{}(%rbp)", self.rbp_offset)),
-                    "char" => self.emit_line(&format!("    movb %al, -{}(%rbp)", self.rbp_offset)),
-                    _ => return Err(format!("unable to store return value for type: {}", var_decl.data_type))
+                    "int" => self.mov(Width::Long, Operand::Reg(Reg::Rax, Width::Long), self.slot(self.rbp_offset)),
+                    "char" => self.mov(Width::Byte, Operand::Reg(Reg::Rax, Width::Byte), self.slot(self.rbp_offset)),
+                    "bool" => self.store_bool(self.slot(self.rbp_offset)),
+                    ty if Self::is_quad(ty) => self.mov(Width::Quad, Operand::Reg(Reg::Rax, Width::Quad), self.slot(self.rbp_offset)),
+                    _ => return Err(format!("unable to initialize a variable of type: {}", var_decl.data_type))
                 }
 
                 Ok(())
             }
-            _ => Ok(())
         }
-        // Ok(())
     }
 
     fn generate_fn_decl(&mut self, func_decl: &FunctionDecl) -> Result<(), String> {
         self.rbp_offset = 0;
+        self.max_rbp_offset = 0;
+        self.current_fn = func_decl.name.clone();
+        self.current_file = func_decl.file.clone();
 
-        let param_regs = ["%edi", "%esi", "%edx", "%ecx", "%r8d", "%r9d"];
-        for (i, param) in func_decl.params.iter().enumerate() {
+        /* parameters share the outermost scope with the locals of the body */
+        self.push_scope();
+        for param in func_decl.params.iter() {
             let size = self.get_type_size(&param.data_type);
-            self.rbp_offset += size;
-            self.variable_offsets.insert(param.name.clone(), self.rbp_offset);
-            self.variable_types.insert(param.name.clone(), param.data_type.clone());
+            self.rbp_offset = self.align_offset(self.rbp_offset + size, size);
+            self.declare_variable(&param.name, &param.data_type);
         }
 
-        self.emit(format!("{}:\n", func_decl.name).as_str());
-        self.emit_line("    pushq %rbp");
-        self.emit_line("    movq %rsp, %rbp");
+        /*
+            Generate the body first, the frame has to cover every local declared in it
+            and we don't know how many there are until the body has been walked.
+        */
+        let mut prologue = String::new();
+        std::mem::swap(&mut self.output, &mut prologue);
+        self.stack_depth = 0;
 
-        if self.rbp_offset > 0 {
-            let stk_size = ((self.rbp_offset + 15) / 16) * 16;
-            self.emit_line(&format!("    subq ${}, %rsp", stk_size));
-        }
-
-        for (i, param) in func_decl.params.iter().enumerate() {
-            if i < 6 {
-                self.save_param_to_stk(param, i)?;
-            } else {
-                return Err("tried to do stack parameters, not implemented".to_string())
+        let doubles: Vec<bool> = func_decl.params.iter().map(|param| param.data_type == "double").collect();
+        for (param, slot) in func_decl.params.iter().zip(classify_args(&doubles)) {
+            match slot {
+                ArgSlot::Int(reg_idx) => self.save_param_to_stk(param, reg_idx)?,
+                ArgSlot::Sse(xmm) => {
+                    let offset = self.get_variable_offset(&param.name)?;
+                    self.movsd(Operand::Xmm(xmm), self.slot(offset));
+                }
+                ArgSlot::Stack(stack_idx) => self.copy_stack_param(param, stack_idx)?,
             }
         }
 
         for stmt in func_decl.body.iter() {
-            self.generate_stmt(&stmt)?;
+            self.generate_stmt(stmt)?;
+        }
+        self.pop_scope();
+
+        /* Falling off the end of main returns 0, like in C */
+        if func_decl.name == "main" && !matches!(func_decl.body.last(), Some(Stmt::Return(_))) {
+            self.mov(Width::Long, Operand::Imm(0), Operand::Reg(Reg::Rax, Width::Long));
+        }
+
+        /* Every return jumps here, so the teardown is only emitted once. */
+        self.emit(&format!(".Lreturn_{}:\n", func_decl.name));
+        self.inst("leave", None, &[]);
+        self.inst("ret", None, &[]);
+
+        let body = std::mem::replace(&mut self.output, prologue);
+
+        /* cold functions are grouped away from the hot path by the linker */
+        let cold = func_decl.attributes.contains(&Attribute::Cold);
+        if cold {
+            self.emit_line(".section .text.unlikely");
+        }
+
+        /* static functions stay local to this object */
+        if !func_decl.is_static {
+            self.emit_line(&format!("    .globl {}", func_decl.name));
+        }
+        self.emit_line(&format!("    .type {}, @function", func_decl.name));
+
+        self.emit(format!("{}:\n", func_decl.name).as_str());
+        self.emit_loc(func_decl.line);
+        self.push(Reg::Rbp);
+        self.mov(Width::Quad, Operand::Reg(Reg::Rsp, Width::Quad), Operand::Reg(Reg::Rbp, Width::Quad));
+
+        let stk_size = self.max_rbp_offset.div_ceil(16) * 16;
+        if stk_size > 0 {
+            self.bin_op(AluOp::Sub, Width::Quad, Operand::Imm(stk_size as i64), Operand::Reg(Reg::Rsp, Width::Quad));
+        }
+        self.frame_sizes.push((func_decl.name.clone(), stk_size));
+
+        self.emit(&body);
+
+        if cold {
+            self.emit_line(".section .text");
         }
 
-        self.emit_line("    leave");
-        self.emit_line("    ret");
         Ok(())
     }
 
     fn generate_function_call(&mut self, callee: &String, args: &[Expr]) -> Result<(), String> {
-        let arg_regs = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+        /*
+            Undeclared functions like printf are external and treated as variadic, an
+            unresolved name is left for the linker to report.
+        */
+        if let Some(sig) = self.functions.get(callee)
+            && sig.param_types.len() != args.len()
+        {
+            return Err(format!("function '{}' expects {} arguments but got {}", callee, sig.param_types.len(), args.len()));
+        }
 
-        for (i, arg) in args.iter().enumerate() {
-            if i < 6 {
-                match arg {
-                    Expr::Identifier(ident) => {
-                        let offset = self.get_variable_offset(ident)?;
-                        let var_type = self.variable_types.get(ident)
-                            .ok_or_else(|| format!("unknown variable type: {}", ident))?;
-
-                        match var_type.as_str() {
-                            "int" => {
-                                let reg_32 = ["%edi", "%esi", "%edx", "%ecx", "%r8d", "%r9d"][i];
-                                self.emit_line(&format!("    movl -{}(%rbp), {}", offset, reg_32));
-                            },
-                            "char*" => {
-                                self.emit_line(&format!("    movq -{}(%rbp), {}", offset, arg_regs[i]));
-                            },
-                            "char" => {
-                                let reg_32 = ["%edi", "%esi", "%edx", "%ecx", "%r8d", "%r9d"][i];
-                                self.emit_line(&format!("    movzbl -{}(%rbp), {}", offset, reg_32));
-                            },
-                            _ => return Err(format!("Unsupported variable type: {}", var_type))
-                        }
-                    },
+        let doubles: Vec<bool> = args.iter().enumerate().map(|(i, arg)| self.arg_is_double(callee, i, arg)).collect();
+        let slots = classify_args(&doubles);
+        let stack_args = slots.iter().filter(|slot| matches!(slot, ArgSlot::Stack(_))).count();
 
-                    Expr::String(st) => {
-                        /* load the string addr */
-                        self.generate_string(st)?;
-                        let label = self.strings.get(st).unwrap();
-                        self.emit_line(&format!("    leaq .LC{}(%rip), {}", label, arg_regs[i]));
-                    },
+        /*
+            %rsp must be 16-byte aligned at every call, variadic ones like printf included
+            since libc may use aligned SSE stores to spill the vector registers. The frame
+            is already rounded up to 16 so rbp_offset never affects this, only stack_depth
+            does: values pushed by an enclosing expression can leave %rsp off by 8.
+            Arguments that don't fit in registers are pushed right to left, so the first of
+            them ends up at 0(%rsp) when the call happens. Any padding has to go below them,
+            before the first push.
+        */
+        let padding = (16 - (self.stack_depth + stack_args * 8) % 16) % 16;
+        if padding != 0 {
+            self.bin_op(AluOp::Sub, Width::Quad, Operand::Imm(padding as i64), Operand::Reg(Reg::Rsp, Width::Quad));
+            self.stack_depth += padding;
+        }
 
-                    Expr::Number(n) => {
-                        self.emit_line(&format!("    movq ${}, {}", *n as i64, arg_regs[i]));
-                    },
+        /* Pushed before the register args are loaded, evaluating them may use any register */
+        for (i, arg) in args.iter().enumerate().rev() {
+            if let ArgSlot::Stack(_) = slots[i] {
+                self.generate_arg(callee, i, arg, doubles[i])?;
+                self.push(Reg::Rax);
+            }
+        }
+
+        /*
+            Anything that isn't a plain value is evaluated up front and kept on the stack,
+            it may itself contain a call that would clobber registers already loaded.
+            Right to left like the stack args, so the first one ends up on top.
+        */
+        let spilled: Vec<bool> = args.iter().enumerate()
+            .map(|(i, arg)| match slots[i] {
+                ArgSlot::Int(_) => !Self::is_atom(arg) || self.is_double(arg),
+                ArgSlot::Sse(_) => !matches!(arg, Expr::Float(_) | Expr::Identifier { .. }) || !self.is_double(arg),
+                ArgSlot::Stack(_) => false,
+            })
+            .collect();
+
+        for (i, arg) in args.iter().enumerate().rev() {
+            if spilled[i] {
+                self.generate_arg(callee, i, arg, doubles[i])?;
+                self.push(Reg::Rax);
+            }
+        }
 
-                    _ => return Err("unsupported arg type".to_string())
+        for (i, arg) in args.iter().enumerate() {
+            match slots[i] {
+                ArgSlot::Int(reg_idx) if spilled[i] => self.pop(ARG_REGS[reg_idx]),
+                ArgSlot::Int(reg_idx) => self.load_int_arg(callee, i, arg, ARG_REGS[reg_idx])?,
+                ArgSlot::Sse(xmm) if spilled[i] => {
+                    self.pop(Reg::Rax);
+                    self.inst_with("movq", "movq", None, &[Operand::Reg(Reg::Rax, Width::Quad), Operand::Xmm(xmm)]);
                 }
-            } else {
-                /* if greater than 6, just push to the stack */
-                /* stack args */
+                ArgSlot::Sse(xmm) => self.load_double_arg(arg, xmm)?,
+                ArgSlot::Stack(_) => {}
             }
         }
 
-        /* variadic functions */
+        /* variadic functions, %al holds the number of vector registers used */
         if callee == "printf" {
-            self.emit_line("    movl $0, %eax");
+            let vector_args = slots.iter().filter(|slot| matches!(slot, ArgSlot::Sse(_))).count();
+            self.mov(Width::Long, Operand::Imm(vector_args as i64), Operand::Reg(Reg::Rax, Width::Long));
         }
 
-        self.emit_line(&format!("    call {}", callee));
+        self.check_call_alignment(callee)?;
+        self.call(callee);
+
+        /* The callee leaves its stack arguments in place, drop them along with the padding */
+        let cleanup = stack_args * 8 + padding;
+        if cleanup != 0 {
+            self.bin_op(AluOp::Add, Width::Quad, Operand::Imm(cleanup as i64), Operand::Reg(Reg::Rsp, Width::Quad));
+            self.stack_depth -= cleanup;
+        }
 
         Ok(())
     }
 
+    /*
+        Evaluates an argument into %rax, a double as its raw bits so it can be pushed.
+    */
+    fn generate_arg(&mut self, callee: &str, idx: usize, arg: &Expr, double: bool) -> Result<(), String> {
+        if double {
+            self.generate_double(arg)?;
+            self.inst_with("movq", "movq", None, &[Operand::Xmm(0), Operand::Reg(Reg::Rax, Width::Quad)]);
+            return Ok(());
+        }
+
+        let width = self.arg_width(callee, idx, arg);
+        self.generate_expr_as(arg, width)
+    }
+
+    /*
+        Loads a plain value straight into its argument register.
+    */
+    fn load_int_arg(&mut self, callee: &str, idx: usize, arg: &Expr, reg: Reg) -> Result<(), String> {
+        match arg {
+            Expr::Identifier { name, line, column } => {
+                let (location, var_type) = self.get_identifier(name, *line, *column)?;
+
+                match var_type.as_str() {
+                    /* an int handed to a long parameter has to be sign extended */
+                    "int" if self.arg_width(callee, idx, arg) == Width::Quad => {
+                        self.inst_with("movslq", "movsxd", Some(Width::Long), &[location, Operand::Reg(reg, Width::Quad)]);
+                    },
+                    "int" => {
+                        self.mov(Width::Long, location, Operand::Reg(reg, Width::Long));
+                    },
+                    ty if Self::is_quad(ty) => {
+                        self.mov(Width::Quad, location, Operand::Reg(reg, Width::Quad));
+                    },
+                    "char" | "bool" => {
+                        self.movzx(location, reg);
+                    },
+                    _ => return Err(format!("Unsupported variable type: {}", var_type))
+                }
+            },
+
+            Expr::String(st) => {
+                /* load the string addr */
+                self.generate_string(st)?;
+                let label = self.strings.get(st).unwrap();
+                self.lea(Operand::Rip(format!(".LC{}", label)), reg);
+            },
+
+            Expr::Int(n) if !Self::fits_imm32(*n) => {
+                self.movabs(*n, reg);
+            },
+
+            Expr::Int(n) => {
+                self.mov(Width::Quad, Operand::Imm(*n), Operand::Reg(reg, Width::Quad));
+            },
+
+            Expr::Char(c) => {
+                self.mov(Width::Long, Operand::Imm(*c as i64), Operand::Reg(reg, Width::Long));
+            },
+
+            _ => return Err("unsupported arg type".to_string())
+        }
+
+        Ok(())
+    }
+
+    /*
+        Loads a double literal or variable straight into its %xmm register.
+    */
+    fn load_double_arg(&mut self, arg: &Expr, xmm: usize) -> Result<(), String> {
+        let location = match arg {
+            Expr::Float(value) => Operand::Rip(self.generate_double_const(*value)),
+            Expr::Identifier { name, line, column } => self.get_identifier(name, *line, *column)?.0,
+            _ => return Err("unsupported arg type".to_string())
+        };
+
+        self.movsd(location, Operand::Xmm(xmm));
+        Ok(())
+    }
+
+    /*
+        Whether an argument is passed as a double, decided by the parameter when the
+        callee is known and by the value itself otherwise.
+    */
+    fn arg_is_double(&self, callee: &str, idx: usize, arg: &Expr) -> bool {
+        match self.functions.get(callee).and_then(|sig| sig.param_types.get(idx)) {
+            Some(data_type) => data_type == "double",
+            None => self.is_double(arg),
+        }
+    }
+
+    /*
+        Width an argument is passed at, a long parameter needs all 64 bits even when the
+        value is an int. Functions that haven't been generated (libc) take the value as is.
+    */
+    fn arg_width(&self, callee: &str, idx: usize, arg: &Expr) -> Width {
+        let param_type = self.functions.get(callee).and_then(|sig| sig.param_types.get(idx));
+
+        match param_type {
+            Some(data_type) if data_type == "long" => Width::Quad,
+            _ => self.expr_width(arg),
+        }
+    }
+
+    /*
+        Arguments that can be loaded straight into their register without evaluating anything.
+    */
+    fn is_atom(arg: &Expr) -> bool {
+        matches!(arg, Expr::Identifier { .. } | Expr::String(_) | Expr::Int(_) | Expr::Char(_))
+    }
+
+    /*
+        An expression evaluated only for its side effects, the value left in %eax is
+        thrown away.
+    */
     fn generate_expr_stmt(&mut self, expr: &Expr) -> Result<(), String> {
+        self.generate_expr(expr)
+    }
+
+    /*
+        Evaluates an expression into %eax (%rax for pointers).
+    */
+    fn generate_expr(&mut self, expr: &Expr) -> Result<(), String> {
+        /* doubles are computed in %xmm0, an integer context takes the truncated value */
+        if self.is_double(expr) {
+            self.generate_double(expr)?;
+            let width = self.expr_width(expr);
+            self.inst_with("cvttsd2si", "cvttsd2si", None, &[Operand::Xmm(0), Operand::Reg(Reg::Rax, width)]);
+            return Ok(());
+        }
+
+        match expr {
+            Expr::Int(n) => self.generate_int(*n),
+            Expr::Float(_) => unreachable!(),
+            Expr::Char(c) => {
+                self.mov(Width::Long, Operand::Imm(*c as i64), Operand::Reg(Reg::Rax, Width::Long));
+                Ok(())
+            }
+            Expr::Identifier { name, line, column } => self.generate_identifier(name, *line, *column),
+            Expr::String(st) => {
+                self.generate_string(st)?;
+                let label = self.strings.get(st).unwrap();
+                self.lea(Operand::Rip(format!(".LC{}", label)), Reg::Rax);
+                Ok(())
+            }
+            Expr::BinaryOp { left, op, right } => self.generate_binary_op(left, op, right),
+            Expr::FunctionCall { callee, args } => self.generate_function_call(callee, args),
+            Expr::Unary { op, operand } => self.generate_unary_op(op, operand),
+            Expr::Index { base, index } => self.generate_index(base, index),
+            Expr::Deref(pointer) => self.generate_deref(pointer),
+            Expr::AddrOf(operand) => self.generate_addr_of(operand),
+            Expr::Comma(exprs) => self.generate_comma(exprs, Self::generate_expr),
+        }
+    }
+
+    /*
+        Evaluates an expression into %rax at the given width, 32 bit results are
+        sign extended when a 64 bit value is wanted.
+    */
+    fn generate_expr_as(&mut self, expr: &Expr, width: Width) -> Result<(), String> {
+        self.generate_expr(expr)?;
+
+        if width == Width::Quad && self.expr_width(expr) != Width::Quad {
+            self.inst_with("cltq", "cdqe", None, &[]);
+        }
+
+        Ok(())
+    }
+
+    /*
+        Width the value of an integer expression is computed at, 64 bits as soon as
+        a long or a literal that doesn't fit in an int is involved, otherwise 32.
+    */
+    fn expr_width(&self, expr: &Expr) -> Width {
         match expr {
-            Expr::FunctionCall { callee, args } => {
-                self.generate_function_call(callee, args)
+            Expr::Int(n) if !Self::fits_imm32(*n) => Width::Quad,
+            Expr::String(_) => Width::Quad,
+            Expr::Identifier { name, .. } => match self.get_variable(name) {
+                Ok((_, data_type)) if Self::is_quad(&data_type) => Width::Quad,
+                _ => Width::Long,
+            },
+            Expr::AddrOf(_) => Width::Quad,
+            Expr::Comma(exprs) => exprs.last().map_or(Width::Long, |expr| self.expr_width(expr)),
+            Expr::Deref(pointer) => match self.pointee_type(pointer) {
+                Some(pointee) if Self::is_quad(&pointee) => Width::Quad,
+                _ => Width::Long,
+            },
+            Expr::FunctionCall { callee, .. } if Self::is_quad(&self.return_type(callee)) => Width::Quad,
+            Expr::BinaryOp { left, op: Binop::Add | Binop::Sub | Binop::Mul | Binop::Div | Binop::Mod, right } => {
+                if self.expr_width(left) == Width::Quad || self.expr_width(right) == Width::Quad {
+                    Width::Quad
+                } else {
+                    Width::Long
+                }
+            }
+            Expr::Unary { op: UnaryOp::Neg, operand } => self.expr_width(operand),
+            Expr::Index { .. } => match self.value_type(expr) {
+                Some(elem_type) if Self::is_quad(&elem_type) => Width::Quad,
+                _ => Width::Long,
             },
-            _ => Ok(())
+            _ => Width::Long,
         }
     }
 
-    fn generate_number(&mut self, n: f64) -> Result<(), String> {
-        todo!("impl number generation")
+    fn fits_imm32(n: i64) -> bool {
+        i32::try_from(n).is_ok()
+    }
+
+    /*
+        Whether an expression has a double value, any arithmetic with a double operand does.
+    */
+    fn is_double(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Float(_) => true,
+            Expr::Identifier { name, .. } => self.get_variable(name).is_ok_and(|(_, data_type)| data_type == "double"),
+            Expr::FunctionCall { callee, .. } => self.return_type(callee) == "double",
+            Expr::BinaryOp { left, op: Binop::Add | Binop::Sub | Binop::Mul | Binop::Div | Binop::Mod, right } => {
+                self.is_double(left) || self.is_double(right)
+            }
+            Expr::Unary { op: UnaryOp::Neg, operand } => self.is_double(operand),
+            Expr::Deref(pointer) => self.pointee_type(pointer).is_some_and(|pointee| pointee == "double"),
+            Expr::Comma(exprs) => exprs.last().is_some_and(|expr| self.is_double(expr)),
+            _ => false,
+        }
+    }
+
+    /*
+        What a call returns, from the function's definition or prototype, or the library
+        table for undeclared external functions, which default to int.
+    */
+    fn return_type(&self, callee: &str) -> String {
+        match self.functions.get(callee) {
+            Some(sig) => sig.return_type.clone(),
+            None => library_return_type(callee).unwrap_or("int").to_string(),
+        }
+    }
+
+    /*
+        Evaluates an expression into %xmm0, integer values are converted.
+    */
+    fn generate_double(&mut self, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::Float(value) => {
+                let label = self.generate_double_const(*value);
+                self.movsd(Operand::Rip(label), Operand::Xmm(0));
+            }
+            Expr::Identifier { name, line, column } if self.is_double(expr) => {
+                let (location, _) = self.get_identifier(name, *line, *column)?;
+                self.movsd(location, Operand::Xmm(0));
+            }
+            Expr::Comma(exprs) if self.is_double(expr) => self.generate_comma(exprs, Self::generate_double)?,
+            Expr::Deref(pointer) if self.is_double(expr) => {
+                self.generate_expr(pointer)?;
+                self.movsd(Operand::Mem(Reg::Rax), Operand::Xmm(0));
+            }
+            /* the callee already returns it in %xmm0 */
+            Expr::FunctionCall { callee, args } if self.is_double(expr) => self.generate_function_call(callee, args)?,
+            Expr::BinaryOp { left, op, right } if self.is_double(expr) => self.generate_double_binary_op(left, op, right)?,
+            /* flip the sign bit, unlike 0 - x this also turns 0.0 into -0.0 */
+            Expr::Unary { op: UnaryOp::Neg, operand } if self.is_double(expr) => {
+                self.generate_double(operand)?;
+                self.inst_with("movq", "movq", None, &[Operand::Xmm(0), Operand::Reg(Reg::Rax, Width::Quad)]);
+                self.inst("btc", Some(Width::Quad), &[Operand::Imm(63), Operand::Reg(Reg::Rax, Width::Quad)]);
+                self.inst_with("movq", "movq", None, &[Operand::Reg(Reg::Rax, Width::Quad), Operand::Xmm(0)]);
+            }
+            _ => {
+                self.generate_expr(expr)?;
+                let width = self.expr_width(expr);
+                self.inst_with("cvtsi2sd", "cvtsi2sd", None, &[Operand::Reg(Reg::Rax, width), Operand::Xmm(0)]);
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+        Evaluates both sides of a double operation, the left ends up in %xmm0 and the
+        right in %xmm1. The left value is kept on the stack while the right one is computed.
+    */
+    fn generate_double_operands(&mut self, left: &Expr, right: &Expr) -> Result<(), String> {
+        self.generate_double(left)?;
+        self.inst_with("movq", "movq", None, &[Operand::Xmm(0), Operand::Reg(Reg::Rax, Width::Quad)]);
+        self.push(Reg::Rax);
+
+        self.generate_double(right)?;
+        self.inst_with("movapd", "movapd", None, &[Operand::Xmm(0), Operand::Xmm(1)]);
+        self.pop(Reg::Rax);
+        self.inst_with("movq", "movq", None, &[Operand::Reg(Reg::Rax, Width::Quad), Operand::Xmm(0)]);
+        Ok(())
+    }
+
+    fn generate_double_binary_op(&mut self, left: &Expr, op: &Binop, right: &Expr) -> Result<(), String> {
+        let mnemonic = match op {
+            Binop::Add => "addsd",
+            Binop::Sub => "subsd",
+            Binop::Mul => "mulsd",
+            Binop::Div => "divsd",
+            _ => return Err(format!("operator {:?} can't be applied to a double in '{}'", op, self.current_fn)),
+        };
+
+        self.generate_double_operands(left, right)?;
+        self.inst_with(mnemonic, mnemonic, None, &[Operand::Xmm(1), Operand::Xmm(0)]);
+        Ok(())
+    }
+
+    /*
+        Compares doubles into a 0/1 int in %eax. ucomisd sets the carry and zero flags like
+        an unsigned compare, and the parity flag when either side is NaN, which compares
+        unequal to everything. < and <= swap the operands so NaN falls out of seta/setae.
+    */
+    fn generate_double_compare(&mut self, left: &Expr, op: &Binop, right: &Expr) -> Result<(), String> {
+        let al = || Operand::Reg(Reg::Rax, Width::Byte);
+        let cl = || Operand::Reg(Reg::Rcx, Width::Byte);
+
+        self.generate_double_operands(left, right)?;
+
+        let (operands, set) = match op {
+            Binop::Gt => ([Operand::Xmm(1), Operand::Xmm(0)], "seta"),
+            Binop::Ge => ([Operand::Xmm(1), Operand::Xmm(0)], "setae"),
+            Binop::Lt => ([Operand::Xmm(0), Operand::Xmm(1)], "seta"),
+            Binop::Le => ([Operand::Xmm(0), Operand::Xmm(1)], "setae"),
+            Binop::Eq | Binop::Ne => ([Operand::Xmm(1), Operand::Xmm(0)], "sete"),
+            _ => return Err(format!("operator {:?} can't be applied to a double in '{}'", op, self.current_fn)),
+        };
+
+        self.inst_with("ucomisd", "ucomisd", None, &operands);
+        match op {
+            Binop::Eq => {
+                self.inst("sete", None, &[al()]);
+                self.inst("setnp", None, &[cl()]);
+                self.bin_op(AluOp::And, Width::Byte, cl(), al());
+            }
+            Binop::Ne => {
+                self.inst("setne", None, &[al()]);
+                self.inst("setp", None, &[cl()]);
+                self.bin_op(AluOp::Or, Width::Byte, cl(), al());
+            }
+            _ => self.inst(set, None, &[al()]),
+        }
+
+        self.movzx(Operand::Reg(Reg::Rax, Width::Byte), Reg::Rax);
+        Ok(())
+    }
+
+    /*
+        Puts a double constant in .rodata, returns its label.
+    */
+    fn generate_double_const(&mut self, value: f64) -> String {
+        let lc = match self.doubles.get(&value.to_bits()) {
+            Some(lc) => *lc,
+            None => {
+                let lc = self.label_count;
+                self.label_count += 1;
+                self.doubles.insert(value.to_bits(), lc);
+
+                self.string_sect.push_str("    .align 8\n");
+                self.string_sect.push_str(&format!(".LC{}:\n", lc));
+                self.string_sect.push_str(&format!("    .double {:?}\n", value));
+                lc
+            }
+        };
+
+        format!(".LC{}", lc)
+    }
+
+    fn generate_unary_op(&mut self, op: &UnaryOp, operand: &Expr) -> Result<(), String> {
+        if let UnaryOp::Not = op
+            && self.is_double(operand)
+        {
+            return self.generate_double_compare(operand, &Binop::Eq, &Expr::Float(0.0));
+        }
+
+        let width = self.expr_width(operand);
+        let rax = Operand::Reg(Reg::Rax, width);
+        self.generate_expr(operand)?;
+
+        match op {
+            UnaryOp::Neg => self.inst("neg", Some(width), &[rax]),
+            UnaryOp::Not => {
+                self.bin_op(AluOp::Cmp, width, Operand::Imm(0), rax);
+                self.inst("sete", None, &[Operand::Reg(Reg::Rax, Width::Byte)]);
+                self.movzx(Operand::Reg(Reg::Rax, Width::Byte), Reg::Rax);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn generate_int(&mut self, n: i64) -> Result<(), String> {
+        if Self::fits_imm32(n) {
+            self.mov(Width::Long, Operand::Imm(n), Operand::Reg(Reg::Rax, Width::Long));
+        } else {
+            self.movabs(n, Reg::Rax);
+        }
+
+        Ok(())
     }
 
     fn generate_string(&mut self, s: &str) -> Result<(), String> {
@@ -241,55 +1243,50 @@ impl CodeGen {
     }
 
     fn save_param_to_stk(&mut self, param: &Parameter, reg_idx: usize) -> Result<(), String> {
-        let offset = self.variable_offsets.get(&param.name).ok_or_else(|| format!("failed to find an offset for parameter '{}'", param.name))?;
-        let (reg, inst) = match param.data_type.as_str() {
-            "char*" => (self.get_64bit_reg(reg_idx)?, "movq"),
-            "int"   => (self.get_32bit_reg(reg_idx)?, "movl"),
-            "char"  => (self.get_8bit_reg(reg_idx)?, "movb"),
+        let offset = self.get_variable_offset(&param.name)?;
+        let width = match param.data_type.as_str() {
+            ty if Self::is_quad(ty) => Width::Quad,
+            "int"   => Width::Long,
+            "char" | "bool" => Width::Byte,
             _       => return Err(format!("unknown data type tried in save_param_to_stk. data type: {}", param.data_type))
         };
 
-        self.emit_line(&format!("    {} {}, -{}(%rbp)", inst, reg, offset));
+        let reg = self.get_arg_reg(reg_idx, width)?;
+        self.mov(width, reg, self.slot(offset));
         Ok(())
     }
 
-    fn get_64bit_reg(&self, idx: usize) -> Result<&'static str, String> {
-        let regs = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
-        regs.get(idx)
-            .copied()
-            .ok_or_else(|| "too many params for registers".to_string())
-    }
+    /*
+        Parameters that didn't get a register were pushed by the caller and sit above the
+        return address, the first at 16(%rbp), the second at 24(%rbp) and so on. They're
+        copied into a local slot so every parameter is addressed the same way.
+    */
+    fn copy_stack_param(&mut self, param: &Parameter, stack_idx: usize) -> Result<(), String> {
+        let offset = self.get_variable_offset(&param.name)?;
+        let width = match param.data_type.as_str() {
+            ty if ty == "double" || Self::is_quad(ty) => Width::Quad,
+            "int"   => Width::Long,
+            "char" | "bool" => Width::Byte,
+            _       => return Err(format!("unknown data type tried in copy_stack_param. data type: {}", param.data_type))
+        };
 
-    fn get_32bit_reg(&self, idx: usize) -> Result<&'static str, String> {
-        let regs = ["%edi", "%esi", "%edx", "%ecx", "%r8d", "%r9d"];
-        regs.get(idx)
-            .copied()
-            .ok_or_else(|| "too many params for registers".to_string())
+        let incoming = Operand::Stack(16 + stack_idx as i64 * 8);
+        self.mov(width, incoming, Operand::Reg(Reg::Rax, width));
+        self.mov(width, Operand::Reg(Reg::Rax, width), self.slot(offset));
+        Ok(())
     }
 
-    fn get_8bit_reg(&self, idx: usize) -> Result<&'static str, String> {
-        let regs = ["%dil", "%sil", "%dl", "%cl", "%r8b", "%r9b"];
-        regs.get(idx)
-            .copied()
+    fn get_arg_reg(&self, idx: usize, width: Width) -> Result<Operand, String> {
+        ARG_REGS.get(idx)
+            .map(|reg| Operand::Reg(*reg, width))
             .ok_or_else(|| "too many params for registers".to_string())
     }
 
-    fn get_param_register(&self, param_type: &str, index: usize) -> &'static str {
-        let regs_64bit = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
-        let regs_32bit = ["%edi", "%esi", "%edx", "%ecx", "%r8d", "%r9d"];
-
-        if param_type == "char*" {
-            regs_64bit[index]
-        } else {
-            regs_32bit[index]
-        }
-    }
-
     fn get_type_size(&self, data_type: &str) -> usize {
         match data_type {
             "int" => 4,
-            "char" => 1,
-            "char*" => 8,
+            "char" | "bool" => 1,
+            ty if ty == "double" || Self::is_quad(ty) => 8,
             _ => 0,
         }
     }
@@ -309,29 +1306,336 @@ impl CodeGen {
             .collect()
     }
 
-    fn generate_identifier(&mut self, ident: &str) -> Result<(), String> {
-        todo!("impl identifier ref")
+    fn generate_identifier(&mut self, name: &str, line: usize, column: usize) -> Result<(), String> {
+        let (location, data_type) = self.get_identifier(name, line, column)?;
+
+        match data_type.as_str() {
+            "int" => self.mov(Width::Long, location, Operand::Reg(Reg::Rax, Width::Long)),
+            "char" | "bool" => self.movzx(location, Reg::Rax),
+            ty if Self::is_quad(ty) => self.mov(Width::Quad, location, Operand::Reg(Reg::Rax, Width::Quad)),
+            _ => return Err(format!("unsupported variable type: {}", data_type)),
+        }
+
+        Ok(())
     }
 
+    /*
+        Evaluates `left op right` into %eax.
+        The left value is pushed while the right side is generated, so nested
+        expressions like (1 + 2) * (3 + 4) can't clobber each other. The same goes for
+        calls, f() + g() keeps f's result on the stack while g runs since g is free
+        to trash %eax and the other caller-saved registers. The push is tracked in
+        stack_depth so the call to g still gets padded to an aligned %rsp.
+    */
     fn generate_binary_op(&mut self, left: &Expr, op: &Binop, right: &Expr) -> Result<(), String> {
-        todo!("impl me in gen binop!")
+        if let Binop::And | Binop::Or = op {
+            return self.generate_logical_op(left, op, right);
+        }
+
+        if self.is_double(left) || self.is_double(right) {
+            return self.generate_double_compare(left, op, right);
+        }
+
+        if let Binop::Add | Binop::Sub = op
+            && (self.pointer_type(left).is_some() || self.pointer_type(right).is_some())
+        {
+            return self.generate_pointer_arith(left, op, right);
+        }
+
+        /* idiv by zero traps at runtime, a literal zero can be caught here instead */
+        if let (Binop::Div | Binop::Mod, Expr::Int(n)) = (op, right)
+            && *n == 0
+        {
+            return Err(format!("division by zero in '{}'", self.current_fn));
+        }
+
+        /* both sides are widened to 64 bits if either of them is a long */
+        let width = if self.expr_width(left) == Width::Quad || self.expr_width(right) == Width::Quad {
+            Width::Quad
+        } else {
+            Width::Long
+        };
+        let eax = Operand::Reg(Reg::Rax, width);
+        let ecx = Operand::Reg(Reg::Rcx, width);
+
+        self.generate_expr_as(left, width)?;
+        self.push(Reg::Rax);
+        self.generate_expr_as(right, width)?;
+        self.mov(width, eax.clone(), ecx.clone());
+        self.pop(Reg::Rax);
+
+        match op {
+            Binop::Add => self.bin_op(AluOp::Add, width, ecx, eax),
+            Binop::Sub => self.bin_op(AluOp::Sub, width, ecx, eax),
+            Binop::Mul => self.bin_op(AluOp::Imul, width, ecx, eax),
+            Binop::Div | Binop::Mod => {
+                /* sign extend %eax into %edx:%eax (%rax into %rdx:%rax) for the divide */
+                match width {
+                    Width::Quad => self.inst_with("cqto", "cqo", None, &[]),
+                    _ => self.inst_with("cltd", "cdq", None, &[]),
+                }
+                self.inst("idiv", Some(width), &[ecx]);
+
+                /* the quotient is left in %eax, the remainder in %edx */
+                if let Binop::Mod = op {
+                    self.mov(width, Operand::Reg(Reg::Rdx, width), eax);
+                }
+            }
+
+            /* Comparisons produce a clean 0/1 int */
+            Binop::And | Binop::Or => unreachable!(),
+
+            Binop::Eq | Binop::Ne | Binop::Lt | Binop::Gt | Binop::Le | Binop::Ge => {
+                let set = match op {
+                    Binop::Eq => "sete",
+                    Binop::Ne => "setne",
+                    Binop::Lt => "setl",
+                    Binop::Gt => "setg",
+                    Binop::Le => "setle",
+                    _ => "setge",
+                };
+
+                self.bin_op(AluOp::Cmp, width, ecx, eax);
+                self.inst(set, None, &[Operand::Reg(Reg::Rax, Width::Byte)]);
+                self.movzx(Operand::Reg(Reg::Rax, Width::Byte), Reg::Rax);
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+        Pointer arithmetic into %rax. The integer side is scaled by the pointee size so
+        `p + 1` points at the next element, and the byte distance between two pointers
+        is divided by it to count the elements in between.
+    */
+    fn generate_pointer_arith(&mut self, left: &Expr, op: &Binop, right: &Expr) -> Result<(), String> {
+        let left_pointer = self.pointer_type(left);
+        let right_pointer = self.pointer_type(right);
+        let pointee = left_pointer.as_deref().or(right_pointer.as_deref()).and_then(|pointer| pointer.strip_suffix('*'));
+        let size = self.get_type_size(pointee.unwrap_or("char")).max(1) as i64;
+        let rax = Operand::Reg(Reg::Rax, Width::Quad);
+        let rcx = Operand::Reg(Reg::Rcx, Width::Quad);
+
+        self.generate_expr_as(left, Width::Quad)?;
+        self.push(Reg::Rax);
+        self.generate_expr_as(right, Width::Quad)?;
+        self.mov(Width::Quad, rax.clone(), rcx.clone());
+        self.pop(Reg::Rax);
+
+        if size > 1 {
+            match (&left_pointer, &right_pointer) {
+                (Some(_), None) => self.bin_op(AluOp::Imul, Width::Quad, Operand::Imm(size), rcx.clone()),
+                (None, Some(_)) => self.bin_op(AluOp::Imul, Width::Quad, Operand::Imm(size), rax.clone()),
+                _ => {}
+            }
+        }
+
+        match op {
+            Binop::Add => self.bin_op(AluOp::Add, Width::Quad, rcx, rax.clone()),
+            _ => self.bin_op(AluOp::Sub, Width::Quad, rcx, rax.clone()),
+        }
+
+        /* the distance is a whole number of elements, sizes are powers of two */
+        if left_pointer.is_some() && right_pointer.is_some() && size > 1 {
+            self.inst("sar", Some(Width::Quad), &[Operand::Imm(size.trailing_zeros() as i64), rax]);
+        }
+
+        Ok(())
+    }
+
+    /*
+        Short circuiting && and ||, the right side is only evaluated when the left
+        one doesn't already decide the result. Leaves a normalized 0/1 in %eax.
+    */
+    fn generate_logical_op(&mut self, left: &Expr, op: &Binop, right: &Expr) -> Result<(), String> {
+        let id = self.label_count;
+        self.label_count += 1;
+
+        let short_label = format!(".Lshort_{}", id);
+        let end_label = format!(".Llogic_end_{}", id);
+        let eax = Operand::Reg(Reg::Rax, Width::Long);
+
+        /* && is decided by a false left side, || by a true one */
+        let short_value = match op {
+            Binop::And => 0,
+            _ => 1,
+        };
+
+        self.jump_on_condition(left, short_value == 1, &short_label)?;
+
+        /* a double right side is compared against 0.0 like `!` does */
+        if self.is_double(right) {
+            self.generate_double_compare(right, &Binop::Ne, &Expr::Float(0.0))?;
+        } else {
+            self.generate_expr(right)?;
+            let width = self.expr_width(right);
+            self.bin_op(AluOp::Cmp, width, Operand::Imm(0), Operand::Reg(Reg::Rax, width));
+            self.inst("setne", None, &[Operand::Reg(Reg::Rax, Width::Byte)]);
+            self.movzx(Operand::Reg(Reg::Rax, Width::Byte), Reg::Rax);
+        }
+        self.jmp(&end_label);
+
+        self.emit(&format!("{}:\n", short_label));
+        self.mov(Width::Long, Operand::Imm(short_value), eax);
+        self.emit(&format!("{}:\n", end_label));
+        Ok(())
+    }
+
+    /*
+        Returns where a variable lives and its data type. Locals are stack slots,
+        extern globals are addressed relative to %rip by their symbol name.
+    */
+    fn get_variable(&self, name: &str) -> Result<(Operand, String), String> {
+        /* innermost scope first, so shadowing declarations win */
+        let local = self.variable_offsets.iter()
+            .zip(self.variable_types.iter())
+            .rev()
+            .find_map(|(offsets, types)| Some((*offsets.get(name)?, types.get(name)?)));
+
+        if let Some((offset, data_type)) = local {
+            return Ok((self.slot(offset), data_type.clone()));
+        }
+
+        match self.globals.get(name) {
+            Some(data_type) => Ok((Operand::Rip(name.to_string()), data_type.clone())),
+            None => Err(format!("undefined variable '{}'", name)),
+        }
+    }
+
+    /*
+        Looks up a variable referenced in an expression, naming the source position
+        of the reference if it was never declared.
+    */
+    fn get_identifier(&self, name: &str, line: usize, column: usize) -> Result<(Operand, String), String> {
+        self.get_variable(name)
+            .map_err(|err| format!("{} at line {}, col {}", err, line, column))
     }
 
     fn get_variable_offset(&self, variable_name: &str) -> Result<usize, String> {
-        self.variable_offsets.get(variable_name).copied().ok_or_else(|| format!("undefined variable: {}", variable_name))
+        self.variable_offsets.iter()
+            .rev()
+            .find_map(|offsets| offsets.get(variable_name).copied())
+            .ok_or_else(|| format!("undefined variable '{}'", variable_name))
     }
 
     fn align_offset(&self, offset: usize, size: usize) -> usize {
         let alg = size.min(8);
-        ((offset + alg - 1) / alg) * alg
+        offset.div_ceil(alg) * alg
     }
 
-    fn emit(&mut self, code: &str) {
-        self.output.push_str(code);
+    /*
+        Emits an instruction whose mnemonic is the same in both syntaxes,
+        AT&T gets the size suffix appended when a width is given.
+    */
+    fn inst(&mut self, op: &str, width: Option<Width>, operands: &[Operand]) {
+        let att = match width {
+            Some(w) => format!("{}{}", op, w.suffix()),
+            None => op.to_string(),
+        };
+
+        self.inst_with(&att, op, width, operands);
+    }
+
+    /*
+        Emits an instruction whose AT&T and Intel mnemonics differ, e.g. movzbl/movzx.
+    */
+    fn inst_with(&mut self, att: &str, intel: &str, width: Option<Width>, operands: &[Operand]) {
+        let line = self.syntax.instruction(att, intel, width, operands);
+        self.emit_line(&line);
+    }
+
+    fn mov(&mut self, width: Width, src: Operand, dst: Operand) {
+        self.inst("mov", Some(width), &[src, dst]);
+    }
+
+    /*
+        Zero extends a byte into the 32-bit view of a register.
+    */
+    fn movzx(&mut self, src: Operand, dst: Reg) {
+        self.inst_with("movzbl", "movzx", Some(Width::Byte), &[src, Operand::Reg(dst, Width::Long)]);
+    }
+
+    /*
+        Loads a 64 bit immediate, mov only takes a sign extended imm32.
+    */
+    fn movabs(&mut self, value: i64, dst: Reg) {
+        self.inst_with("movabsq", "movabs", None, &[Operand::Imm(value), Operand::Reg(dst, Width::Quad)]);
+    }
+
+    fn movsd(&mut self, src: Operand, dst: Operand) {
+        self.inst_with("movsd", "movsd", Some(Width::Quad), &[src, dst]);
+    }
+
+    fn lea(&mut self, src: Operand, dst: Reg) {
+        self.inst_with("leaq", "lea", None, &[src, Operand::Reg(dst, Width::Quad)]);
+    }
+
+    fn bin_op(&mut self, op: AluOp, width: Width, src: Operand, dst: Operand) {
+        self.inst(op.mnemonic(), Some(width), &[src, dst]);
     }
 
-    fn emit_label_with_code(&mut self, label: &str, code: &str) {
-        self.output.insert_str(0, format!(".{}: {}\n", label, code).as_str());
+    fn push(&mut self, reg: Reg) {
+        self.inst("push", Some(Width::Quad), &[Operand::Reg(reg, Width::Quad)]);
+        self.stack_depth += 8;
+    }
+
+    fn pop(&mut self, reg: Reg) {
+        self.inst("pop", Some(Width::Quad), &[Operand::Reg(reg, Width::Quad)]);
+        self.stack_depth -= 8;
+    }
+
+    /*
+        The System V ABI requires %rsp to be 16-byte aligned at every call.
+        The prologue leaves it aligned (push %rbp plus a frame rounded up to 16),
+        so only the bytes pushed since then can throw it off. This is an internal
+        invariant, checked in debug builds so codegen bugs surface at compile time
+        instead of as crashes inside callees that use aligned SSE moves.
+    */
+    fn check_call_alignment(&self, callee: &str) -> Result<(), String> {
+        if cfg!(debug_assertions) && !self.stack_depth.is_multiple_of(16) {
+            return Err(format!(
+                "internal error: stack misaligned by {} bytes at call to '{}' in '{}'",
+                self.stack_depth % 16, callee, self.current_fn
+            ));
+        }
+
+        Ok(())
+    }
+
+    /*
+        Functions that aren't defined in this file, like libc's, are called through the
+        PLT so the executable can be position independent.
+    */
+    fn call(&mut self, callee: &str) {
+        let target = if self.functions.get(callee).is_some_and(|sig| !sig.external) {
+            callee.to_string()
+        } else {
+            format!("{}@PLT", callee)
+        };
+        self.inst("call", None, &[Operand::Label(target)]);
+    }
+
+    fn jmp(&mut self, label: &str) {
+        self.inst("jmp", None, &[Operand::Label(label.to_string())]);
+    }
+
+    /*
+        Conditional jump, `op` is the jcc mnemonic (je, jne, ...).
+    */
+    fn jump_if(&mut self, op: &str, label: &str) {
+        self.inst(op, None, &[Operand::Label(label.to_string())]);
+    }
+
+    /*
+        Operand for a variable's stack slot, offsets are stored as positive distances below %rbp.
+    */
+    fn slot(&self, offset: usize) -> Operand {
+        Operand::Stack(-(offset as i64))
+    }
+
+    fn emit(&mut self, code: &str) {
+        self.output.push_str(code);
     }
 
     fn emit_line(&mut self, code: &str) {
@@ -340,28 +1644,74 @@ impl CodeGen {
         self.output.push('\n');
     }
 
+    /*
+        Attributes the following instructions to a source line, only with debug info enabled.
+    */
+    fn emit_loc(&mut self, line: usize) {
+        if self.debug_file.is_some() {
+            self.emit_line(&format!("    .loc 1 {}", line));
+        }
+    }
+
     fn emit_indent(&mut self) {
         for _ in 0..self.isize {
             self.output.push_str("    ");
         }
     }
 
-    fn inc_indent(&mut self) {
-        self.isize += 1;
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+        Lines a fresh CodeGen emits for whatever `emit` does.
+    */
+    fn emitted(syntax: Syntax, emit: impl FnOnce(&mut CodeGen)) -> Vec<String> {
+        let mut codegen = CodeGen::new().with_syntax(syntax);
+        emit(&mut codegen);
+        codegen.output.lines().map(|line| line.trim().to_string()).collect()
     }
 
-    fn dec_ident(&mut self) {
-        if self.isize > 0 {
-            self.isize -= 1;
-        }
+    fn sample(codegen: &mut CodeGen) {
+        codegen.mov(Width::Long, Operand::Imm(5), Operand::Stack(-4));
+        codegen.mov(Width::Quad, Operand::Reg(Reg::Rax, Width::Quad), Operand::Reg(Reg::Rcx, Width::Quad));
+        codegen.lea(Operand::Rip(".LC0".to_string()), Reg::Rdi);
+        codegen.bin_op(AluOp::Add, Width::Long, Operand::Reg(Reg::Rcx, Width::Long), Operand::Reg(Reg::Rax, Width::Long));
+        codegen.bin_op(AluOp::Cmp, Width::Quad, Operand::Imm(0), Operand::Stack(-16));
+        codegen.movzx(Operand::Mem(Reg::Rax), Reg::Rax);
+        codegen.movsd(Operand::Rip(".LD1".to_string()), Operand::Xmm(0));
+        codegen.push(Reg::Rax);
+        codegen.pop(Reg::Rdx);
     }
 
-    pub fn get_output(&self) -> &str {
-        &self.output
+    #[test]
+    fn helpers_format_att_instructions() {
+        assert_eq!(emitted(Syntax::Att, sample), [
+            "movl $5, -4(%rbp)",
+            "movq %rax, %rcx",
+            "leaq .LC0(%rip), %rdi",
+            "addl %ecx, %eax",
+            "cmpq $0, -16(%rbp)",
+            "movzbl (%rax), %eax",
+            "movsd .LD1(%rip), %xmm0",
+            "pushq %rax",
+            "popq %rdx",
+        ]);
     }
 
-    pub fn clear(&mut self) {
-        self.output.clear();
-        self.isize = 0;
+    #[test]
+    fn helpers_format_intel_instructions() {
+        assert_eq!(emitted(Syntax::Intel, sample), [
+            "mov DWORD PTR [rbp-4], 5",
+            "mov rcx, rax",
+            "lea rdi, [rip+.LC0]",
+            "add eax, ecx",
+            "cmp QWORD PTR [rbp-16], 0",
+            "movzx eax, BYTE PTR [rax]",
+            "movsd xmm0, QWORD PTR [rip+.LD1]",
+            "push rax",
+            "pop rdx",
+        ]);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,50 @@
+use std::fmt;
+
+/*
+    An error reported by one of the compiler's stages, with its location as data.
+    A line or column of 0 means the position isn't known, e.g. at the end of the input.
+    Past the preprocessor the file is only set when a #line marker named one, otherwise
+    it's empty.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum CrustyError {
+    Preprocess { msg: String, file: String, line: usize },
+    Lex { msg: String, file: String, line: usize, col: usize },
+    Parse { msg: String, file: String, line: usize, col: usize },
+    Type { msg: String, file: String, line: usize, col: usize },
+    Codegen { msg: String, file: String, line: usize },
+}
+
+/*
+    Every stage formats its location the same way, "line 4, col 12: msg" when the column
+    is known and "line 4: msg" when it isn't, the prefix is added by whoever reports it.
+*/
+impl fmt::Display for CrustyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (msg, file, line, col) = match self {
+            CrustyError::Preprocess { msg, file, line }
+            | CrustyError::Codegen { msg, file, line } => (msg, file, *line, 0),
+            CrustyError::Lex { msg, file, line, col }
+            | CrustyError::Parse { msg, file, line, col }
+            | CrustyError::Type { msg, file, line, col } => (msg, file, *line, *col),
+        };
+
+        match (line, col) {
+            (0, _) => write!(f, "{}", msg),
+            (_, 0) => write!(f, "{}: {}", location(file, line), msg),
+            _ => write!(f, "{}, col {}: {}", location(file, line), col, msg),
+        }
+    }
+}
+
+impl std::error::Error for CrustyError {}
+
+/*
+    "line 100", or "line 100 of gen.cr" after a #line marker named the file.
+*/
+fn location(file: &str, line: usize) -> String {
+    match file {
+        "" => format!("line {}", line),
+        _ => format!("line {} of {}", line, file),
+    }
+}
@@ -1,11 +1,18 @@
 use std::collections::HashMap;
+use crate::error::CrustyError;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     Identifier,
     Number,
+    Float,
     String,
+    Char,
     Equals,
+    PlusEq,
+    MinusEq,
+    StarEq,
+    SlashEq,
     DataType,
     Colon,
     Semi,
@@ -13,13 +20,36 @@ pub enum TokenType {
     Sub,
     Mul,
     Div,
+    Mod,
+    EqEq,
+    NotEq,
+    Lt,
+    Gt,
+    LtEq,
+    GtEq,
+    AndAnd,
+    Amp,
+    OrOr,
+    Bang,
     LParen,
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     Comma,
     Return,
-    EOF,
+    Restrict,
+    Extern,
+    Static,
+    Register,
+    If,
+    Else,
+    While,
+    True,
+    False,
+    At,
+    Eof,
 }
 
 #[derive(Debug, Clone)]
@@ -28,16 +58,18 @@ pub struct Token {
     pub lexeme: String,
     pub line: usize,
     pub column: usize,
+    pub file: String,   /* named by the last #line marker, empty if there was none */
 }
 
 #[derive(Debug, Clone)]
 pub struct Lexer<'a> {
-    source: &'a str,
     chars: std::str::Chars<'a>,
     current: Option<char>,
-    pos: usize,
     line: usize,
     column: usize,
+    token_line: usize,      /* where the token being lexed starts */
+    token_column: usize,
+    file: String,   /* set by #line markers from the preprocessor */
     keywords: HashMap<&'a str, TokenType>,
 }
 
@@ -46,21 +78,37 @@ impl<'a> Lexer<'a> {
         let mut keywords = HashMap::new();
 
         keywords.insert("return", TokenType::Return);
+        keywords.insert("restrict", TokenType::Restrict);
+        keywords.insert("extern", TokenType::Extern);
+        keywords.insert("static", TokenType::Static);
+        keywords.insert("register", TokenType::Register);
+        keywords.insert("if", TokenType::If);
+        keywords.insert("else", TokenType::Else);
+        keywords.insert("while", TokenType::While);
+        keywords.insert("true", TokenType::True);
+        keywords.insert("false", TokenType::False);
         
         keywords.insert("int", TokenType::DataType);
         keywords.insert("char", TokenType::DataType);
         keywords.insert("char*", TokenType::DataType);
+        keywords.insert("int*", TokenType::DataType);
+        keywords.insert("long*", TokenType::DataType);
+        keywords.insert("double*", TokenType::DataType);
+        keywords.insert("bool", TokenType::DataType);
+        keywords.insert("long", TokenType::DataType);
+        keywords.insert("double", TokenType::DataType);
 
         let mut chars = source.chars();
         let current = chars.next();
 
         Self {
-            source,
             chars,
             current,
-            pos: 0,
             line: 1,
             column: 1,
+            token_line: 1,
+            token_column: 1,
+            file: String::new(),
             keywords,
         }
     }
@@ -69,13 +117,15 @@ impl<'a> Lexer<'a> {
         Main processor for returning the
         next token in the source code.
     */
-    fn next_token(&mut self) -> Result<Token, String> {
+    pub fn next_token(&mut self) -> Result<Token, CrustyError> {
         loop {
             self.skip_whitespace();
+            self.token_line = self.line;
+            self.token_column = self.column;
 
             let ch = match self.current {
                 Some(c) => c,
-                None => return Ok(self.make(TokenType::EOF, String::new())),
+                None => return Ok(self.make(TokenType::Eof, String::new())),
             };
 
             /* Skip comments */
@@ -84,21 +134,39 @@ impl<'a> Lexer<'a> {
                 continue;
             }
 
+            if ch == '/' && self.peek() == Some('*') {
+                self.skip_block_comment()?;
+                continue;
+            }
+
+            /* Line markers forwarded by the preprocessor */
+            if ch == '#' {
+                self.process_line_marker()?;
+                continue;
+            }
+
             let token = match ch {
-                /* Process string literals start with " or ' */
-                '"' | '\'' => {
+                /* Process string literals */
+                '"' => {
                     let value = self.process_string()?;
                     self.make(TokenType::String, value)
                 }
 
+                /* Process character literals, exactly one (possibly escaped) character */
+                '\'' => {
+                    let value = self.process_char()?;
+                    self.make(TokenType::Char, value)
+                }
+
                 /* Process numeric literals */
                 c if c.is_ascii_digit() => {
-                    let value = self.process_numeric();
-                    self.make(TokenType::Number, value)
+                    let value = self.process_numeric()?;
+                    let typ = if value.contains('.') { TokenType::Float } else { TokenType::Number };
+                    self.make(typ, value)
                 }
 
-                /* Process identifiers and keywords (if they exist) */
-                c if c.is_ascii_alphabetic() || c == '_' => {
+                /* Process identifiers and keywords (if they exist), any Unicode letter may start one */
+                c if c.is_alphabetic() || c == '_' => {
                     let value = self.process_identifier();
                     let typ = self
                         .keywords
@@ -108,6 +176,69 @@ impl<'a> Lexer<'a> {
                     self.make(typ, value)
                 }
 
+                '=' if self.peek() == Some('=') => {
+                    self.advance();
+                    self.advance();
+                    self.make(TokenType::EqEq, "==".to_string())
+                }
+
+                '!' if self.peek() == Some('=') => {
+                    self.advance();
+                    self.advance();
+                    self.make(TokenType::NotEq, "!=".to_string())
+                }
+
+                '!' => {
+                    self.advance();
+                    self.make(TokenType::Bang, ch.to_string())
+                }
+
+                '<' | '>' if self.peek() == Some('=') => {
+                    self.advance();
+                    self.advance();
+                    let typ = if ch == '<' { TokenType::LtEq } else { TokenType::GtEq };
+                    self.make(typ, format!("{}=", ch))
+                }
+
+                '+' | '-' | '*' | '/' if self.peek() == Some('=') => {
+                    self.advance();
+                    self.advance();
+                    let typ = match ch {
+                        '+' => TokenType::PlusEq,
+                        '-' => TokenType::MinusEq,
+                        '*' => TokenType::StarEq,
+                        _ => TokenType::SlashEq,
+                    };
+                    self.make(typ, format!("{}=", ch))
+                }
+
+                '&' if self.peek() == Some('&') => {
+                    self.advance();
+                    self.advance();
+                    self.make(TokenType::AndAnd, "&&".to_string())
+                }
+
+                '&' => {
+                    self.advance();
+                    self.make(TokenType::Amp, ch.to_string())
+                }
+
+                '|' if self.peek() == Some('|') => {
+                    self.advance();
+                    self.advance();
+                    self.make(TokenType::OrOr, "||".to_string())
+                }
+
+                '<' => {
+                    self.advance();
+                    self.make(TokenType::Lt, ch.to_string())
+                }
+
+                '>' => {
+                    self.advance();
+                    self.make(TokenType::Gt, ch.to_string())
+                }
+
                 '=' => {
                     self.advance();
                     self.make(TokenType::Equals, ch.to_string())
@@ -148,6 +279,11 @@ impl<'a> Lexer<'a> {
                     self.make(TokenType::Mul, ch.to_string())
                 }
 
+                '%' => {
+                    self.advance();
+                    self.make(TokenType::Mod, ch.to_string())
+                }
+
                 '(' => {
                     self.advance();
                     self.make(TokenType::LParen, ch.to_string())
@@ -168,9 +304,24 @@ impl<'a> Lexer<'a> {
                     self.make(TokenType::RBrace, ch.to_string())
                 }
 
+                '[' => {
+                    self.advance();
+                    self.make(TokenType::LBracket, ch.to_string())
+                }
+
+                ']' => {
+                    self.advance();
+                    self.make(TokenType::RBracket, ch.to_string())
+                }
+
+                '@' => {
+                    self.advance();
+                    self.make(TokenType::At, ch.to_string())
+                }
+
                 _ => {
                     self.advance();
-                    return Err(format!("[twee::error] unknown character '{}'", ch));
+                    return Err(self.error(format!("unknown character '{}'", ch)));
                 }
             };
 
@@ -179,14 +330,49 @@ impl<'a> Lexer<'a> {
     }
 
     /*
-        Wrapper for next_token to return a token, or an error token.
+        Lexes the rest of the input, up to and including the Eof token.
+    */
+    pub fn tokens(&mut self) -> Result<Vec<Token>, CrustyError> {
+        let mut tokens = vec![];
+
+        loop {
+            let token = self.next_token()?;
+            let done = token.token_type == TokenType::Eof;
+            tokens.push(token);
+
+            if done {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    /*
+        Handles a `#line N "file"` marker, the line following it becomes line N.
     */
-    pub fn next(&mut self) -> Option<Token> {
-        match self.next_token() {
-            Ok(t) => Some(t),
-            // Err(e) => Some(),
-            Err(_) => None,
+    fn process_line_marker(&mut self) -> Result<(), CrustyError> {
+        let mut marker = String::new();
+        while let Some(ch) = self.current() {
+            self.advance();
+            if ch == '\n' {
+                break;
+            }
+
+            marker.push(ch);
         }
+
+        let mut parts = marker.trim_start_matches('#').trim().splitn(3, char::is_whitespace);
+        let line = match (parts.next(), parts.next().and_then(|n| n.parse::<usize>().ok())) {
+            (Some("line"), Some(line)) => line,
+            _ => return Err(self.error(format!("invalid line marker '{}'", marker))),
+        };
+
+        if let Some(file) = parts.next() {
+            self.file = file.trim().trim_matches('"').to_string();
+        }
+
+        self.line = line;
+        self.column = 1;
+        Ok(())
     }
 
     /*
@@ -218,13 +404,41 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /*
+        Skip a C style comment up to and including the closing marker,
+        it may span several lines.
+    */
+    fn skip_block_comment(&mut self) -> Result<(), CrustyError> {
+        /* step over the opening slash and star */
+        self.advance();
+        self.advance();
+
+        while let Some(ch) = self.current() {
+            self.advance();
+            if ch == '*' && self.current == Some('/') {
+                self.advance();
+                return Ok(());
+            }
+        }
+
+        Err(self.error("unterminated block comment".to_string()))
+    }
+
+    /*
+        An error at the start of the token being lexed.
+    */
+    fn error(&self, msg: String) -> CrustyError {
+        CrustyError::Lex { msg, file: self.file.clone(), line: self.token_line, col: self.token_column }
+    }
+
     /*
         Helper for initiating a new token.
     */
     fn make(&mut self, token_type: TokenType, lexeme: String) -> Token {
         Token {
-            line: self.line,
-            column: self.column,
+            line: self.token_line,
+            column: self.token_column,
+            file: self.file.clone(),
             token_type,
             lexeme,
         }
@@ -234,23 +448,34 @@ impl<'a> Lexer<'a> {
         Returns the character one position ahead of the current character.
     */
     fn peek(&self) -> Option<char> {
-        let mut chars = self.chars.clone();
-        chars.next()
+        self.peek_n(1)
     }
 
     /*
-        Returns a character depending on the position.
+        Returns the character n positions ahead without consuming anything, peek_n(0) is
+        the current character. The iterator is already at the current position, so only
+        the n characters in between are walked over.
     */
-    fn current(&mut self) -> Option<char> {
-        self.source.chars().nth(self.pos)
+    fn peek_n(&self, n: usize) -> Option<char> {
+        match n {
+            0 => self.current,
+            _ => self.chars.clone().nth(n - 1),
+        }
+    }
+
+    /*
+        Returns the character at the current position, kept in sync by advance().
+    */
+    fn current(&self) -> Option<char> {
+        self.current
     }
 
     /*
         Advances to the next character by incrementing the position.
+        The line only changes once the '\n' itself has been consumed.
     */
     fn advance(&mut self) {
-        self.pos += 1;
-        if self.current() == Some('\n') {
+        if self.current == Some('\n') {
             self.line += 1;
             self.column = 1;
         } else {
@@ -263,7 +488,7 @@ impl<'a> Lexer<'a> {
     /*
         This function is responsible for processing a string literal.
     */
-    fn process_string(&mut self) -> Result<String, String> {
+    fn process_string(&mut self) -> Result<String, CrustyError> {
         /* Track the opening quote so we can properly terminate the string. */
         let opening_quote = self.current();
         self.advance();
@@ -294,7 +519,7 @@ impl<'a> Lexer<'a> {
                         value.push(c);
                     }
 
-                    None => return Err("Unterminated string literal".to_string()),
+                    None => return Err(self.error("unterminated string literal".to_string())),
                 }
             } else {
                 value.push(ch);
@@ -306,13 +531,60 @@ impl<'a> Lexer<'a> {
         /*
             Assume the string is unterminated, 9/10 it is.
         */
-        Err("Unterminated string literal".to_string())
+        Err(self.error("unterminated string literal".to_string()))
+    }
+
+    /*
+        This function is responsible for processing a character literal.
+    */
+    fn process_char(&mut self) -> Result<String, CrustyError> {
+        self.advance();
+
+        let value = match self.current {
+            Some('\\') => {
+                self.advance();
+                match self.current {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('\\') => '\\',
+                    Some('\'') => '\'',
+                    Some('"') => '"',
+                    Some('0') => '\0',
+                    Some(c) => return Err(self.error(format!("unknown escape sequence '\\{}' in character literal", c))),
+                    None => return Err(self.error("unterminated character literal".to_string())),
+                }
+            }
+            Some('\'') => return Err(self.error("empty character literal".to_string())),
+            Some(c) => c,
+            None => return Err(self.error("unterminated character literal".to_string())),
+        };
+
+        self.advance();
+        if self.current != Some('\'') {
+            return Err(self.error("character literal must contain exactly one character".to_string()));
+        }
+
+        self.advance();
+        Ok(value.to_string())
     }
 
     /*
         This function is responsible for processing a numeric literal.
     */
-    fn process_numeric(&mut self) -> String {
+    fn process_numeric(&mut self) -> Result<String, CrustyError> {
+        /* 0x, 0b and 0o prefixes pick another radix, the prefix stays in the lexeme */
+        let radix = match (self.current, self.peek()) {
+            (Some('0'), Some('x' | 'X')) => Some((16, "hexadecimal")),
+            (Some('0'), Some('b' | 'B')) => Some((2, "binary")),
+            (Some('0'), Some('o' | 'O')) => Some((8, "octal")),
+            _ => None,
+        };
+
+        if let Some((radix, name)) = radix {
+            return self.process_radix_numeric(radix, name);
+        }
+
         let mut value = String::new();
         /* Track if this is a floating point numeric literal */
         let mut floating = false;
@@ -326,11 +598,11 @@ impl<'a> Lexer<'a> {
                 if all fails, break.
             */
             match ch {
-                c if c.is_numeric() => {
+                c if c.is_ascii_digit() => {
                     self.advance();
                     value.push(c);
                 }
-                '.' if !floating && self.peek().map_or(false, |n| n.is_numeric()) => {
+                '.' if !floating && self.peek().is_some_and(|n| n.is_ascii_digit()) => {
                     floating = true;
                     value.push(ch);
                     self.advance();
@@ -339,7 +611,43 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        value
+        Ok(value)
+    }
+
+    /*
+        Reads a prefixed integer literal such as 0xFF, every digit has to be valid in the radix.
+    */
+    fn process_radix_numeric(&mut self, radix: u32, name: &str) -> Result<String, CrustyError> {
+        let mut value = String::new();
+
+        /* the 0 and the prefix letter */
+        for _ in 0..2 {
+            value.extend(self.current);
+            self.advance();
+        }
+
+        let start = value.len();
+        while let Some(ch) = self.current() {
+            if ch.is_digit(radix) {
+                value.push(ch);
+                self.advance();
+            } else if ch.is_alphanumeric() {
+                return Err(CrustyError::Lex {
+                    msg: format!("invalid digit '{}' in {} literal", ch, name),
+                    file: self.file.clone(),
+                    line: self.line,
+                    col: self.column,
+                });
+            } else {
+                break;
+            }
+        }
+
+        if value.len() == start {
+            return Err(self.error(format!("{} literal has no digits", name)));
+        }
+
+        Ok(value)
     }
 
     /*
@@ -349,7 +657,7 @@ impl<'a> Lexer<'a> {
         let mut value = String::new();
 
         while let Some(ch) = self.current() {
-            if ch.is_ascii_alphanumeric() || ch == '_' || ch == '*' {
+            if ch.is_alphanumeric() || ch == '_' {
                 value.push(ch);
                 self.advance();
             } else {
@@ -357,7 +665,78 @@ impl<'a> Lexer<'a> {
             }
         }
 
+        /* a pointer type like `char*`, anywhere else the '*' is a multiplication or dereference */
+        if self.current() == Some('*') && self.keywords.contains_key(format!("{}*", value).as_str()) {
+            value.push('*');
+            self.advance();
+        }
+
         /* Just return the identifier as a string, will be handled elsewhere */
         value
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_types(source: &str) -> Vec<TokenType> {
+        Lexer::new(source).tokens().unwrap().into_iter().map(|token| token.token_type).collect()
+    }
+
+    #[test]
+    fn return_is_a_keyword() {
+        assert_eq!(token_types("return 42;"), vec![TokenType::Return, TokenType::Number, TokenType::Semi, TokenType::Eof]);
+    }
+
+    #[test]
+    fn newline_starts_the_next_line_at_column_one() {
+        let tokens = Lexer::new("a\nb").tokens().unwrap();
+
+        assert_eq!((tokens[0].line, tokens[0].column), (1, 1));
+        assert_eq!((tokens[1].lexeme.as_str(), tokens[1].line, tokens[1].column), ("b", 2, 1));
+    }
+
+    #[test]
+    fn peek_n_looks_ahead_without_consuming() {
+        let lexer = Lexer::new("abc");
+
+        assert_eq!(lexer.peek_n(0), Some('a'));
+        assert_eq!(lexer.peek(), Some('b'));
+        assert_eq!(lexer.peek_n(2), Some('c'));
+        assert_eq!(lexer.peek_n(3), None);
+        assert_eq!(lexer.current(), Some('a'));
+    }
+
+    #[test]
+    fn line_marker_renumbers_the_following_tokens() {
+        let tokens = Lexer::new("x\n#line 100 \"gen.cr\"\ny\n  z").tokens().unwrap();
+
+        assert_eq!((tokens[0].line, tokens[0].file.as_str()), (1, ""));
+        assert_eq!((tokens[1].line, tokens[1].column, tokens[1].file.as_str()), (100, 1, "gen.cr"));
+        assert_eq!((tokens[2].line, tokens[2].column), (101, 3));
+    }
+
+    #[test]
+    fn invalid_line_marker_is_an_error() {
+        let error = Lexer::new("#line x\n").tokens().unwrap_err();
+
+        assert!(matches!(error, CrustyError::Lex { line: 1, .. }), "{:?}", error);
+    }
+
+    #[test]
+    fn unicode_letters_form_identifiers() {
+        let tokens = Lexer::new("int café = naïve;").tokens().unwrap();
+
+        assert_eq!((tokens[1].token_type.clone(), tokens[1].lexeme.as_str()), (TokenType::Identifier, "café"));
+        assert_eq!((tokens[2].token_type.clone(), tokens[2].column), (TokenType::Equals, 10));
+        assert_eq!(tokens[3].lexeme, "naïve");
+    }
+
+    #[test]
+    fn combining_character_cannot_start_an_identifier() {
+        let error = Lexer::new("\u{301}x").tokens().unwrap_err();
+
+        assert!(matches!(error, CrustyError::Lex { line: 1, col: 1, .. }), "{:?}", error);
+    }
+}
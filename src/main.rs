@@ -1,14 +1,243 @@
 use colored::Colorize;
 use std::fs::{read_to_string, write};
+use std::io::IsTerminal;
+use std::path::Path;
 use std::process::Command;
+use crate::asm::Syntax;
 use crate::codegen::CodeGen;
+use crate::error::CrustyError;
 use crate::lexer::Lexer;
 use crate::parser::Parser;
+use crate::preprocessor::Preprocessor;
 
+mod asm;
 mod ast;
 mod lexer;
 mod parser;
 mod codegen;
+mod error;
+mod optimize;
+mod preprocessor;
+mod pretty;
+mod typecheck;
+
+/*
+    Options collected from the command line.
+*/
+struct Options {
+    input: String,
+    output: Option<String>,
+    assembly_only: bool,
+    optimize_tail_strings: bool,
+    fold_constants: bool,
+    pedantic: bool,
+    print_frame_sizes: bool,
+    check: bool,
+    emit_tokens: bool,
+    emit_ast: bool,
+    debug_info: bool,
+    color: bool,
+    syntax: Syntax,
+    sanitizers: Vec<String>,
+    library_paths: Vec<String>,
+    libraries: Vec<String>,
+    link_args: Vec<String>,
+}
+
+impl Options {
+    /*
+        Long flags start with "--", the gcc-style ones with a single dash: -o, -S, -g,
+        -O0/-O1, -l and -L. A flag that takes a value reads it from the next argument
+        (-o out, --syntax intel), -l and -L also accept it joined (-lm, -L/usr/lib) and
+        --color as --color=never. Everything else is joined to form the input path.
+    */
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut paths = vec![];
+        let mut options = Options {
+            input: String::new(),
+            output: None,
+            assembly_only: false,
+            optimize_tail_strings: false,
+            fold_constants: false,
+            pedantic: false,
+            print_frame_sizes: false,
+            check: false,
+            emit_tokens: false,
+            emit_ast: false,
+            debug_info: false,
+            color: std::io::stdout().is_terminal(),
+            syntax: Syntax::Att,
+            sanitizers: vec![],
+            library_paths: vec![],
+            libraries: vec![],
+            link_args: vec![],
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--optimize-tail-strings" => options.optimize_tail_strings = true,
+                "-O0" => options.fold_constants = false,
+                "-O1" => options.fold_constants = true,
+                "--pedantic" => options.pedantic = true,
+                "--print-frame-sizes" => options.print_frame_sizes = true,
+                "--check" => options.check = true,
+                "--emit-tokens" => options.emit_tokens = true,
+                "--emit-ast" => options.emit_ast = true,
+                "-S" => options.assembly_only = true,
+                "-g" => options.debug_info = true,
+                "-o" => {
+                    let output = args.next().ok_or("-o expects an output file name")?;
+                    options.output = Some(output.clone());
+                }
+                "--syntax" => {
+                    let name = args.next().ok_or("--syntax expects 'att' or 'intel'")?;
+                    options.syntax = Syntax::parse(name)?;
+                }
+                "--sanitize" => {
+                    let name = args.next().ok_or("--sanitize expects 'address' or 'undefined'")?;
+                    if !["address", "undefined"].contains(&name.as_str()) {
+                        return Err(format!("unknown sanitizer '{}', expected 'address' or 'undefined'", name));
+                    }
+
+                    options.sanitizers.push(name.clone());
+                }
+                /* --color=always|auto|never, --color <when> works too */
+                flag if flag == "--color" || flag.starts_with("--color=") => {
+                    let when = match flag.strip_prefix("--color=") {
+                        Some(when) => when,
+                        None => args.next().ok_or("--color expects 'always', 'auto' or 'never'")?.as_str(),
+                    };
+
+                    options.color = match when {
+                        "always" => true,
+                        "never" => false,
+                        "auto" => std::io::stdout().is_terminal(),
+                        _ => return Err(format!("unknown color mode '{}', expected 'always', 'auto' or 'never'", when)),
+                    };
+                }
+                "--link-arg" => {
+                    let link_arg = args.next().ok_or("--link-arg expects an argument for the linker")?;
+                    options.link_args.push(link_arg.clone());
+                }
+                /* -lm / -l m and -L/usr/lib / -L /usr/lib, same as gcc */
+                flag if flag.starts_with("-l") || flag.starts_with("-L") => {
+                    let value = match &flag[2..] {
+                        "" => args.next().ok_or(format!("{} expects a value", flag))?.as_str(),
+                        value => value,
+                    };
+
+                    if flag.starts_with("-l") {
+                        options.libraries.push(value.to_string());
+                    } else {
+                        options.library_paths.push(value.to_string());
+                    }
+                }
+                _ => paths.push(arg.clone()),
+            }
+        }
+
+        options.input = paths.join(" ");
+        Ok(options)
+    }
+
+    /*
+        Where the generated assembly is written. With -S it is the output itself,
+        named after the input unless -o says otherwise. Without -o it's kept next to
+        the output as out.s, with -o it's only an intermediate so it goes to a temp file.
+    */
+    fn asm_path(&self) -> String {
+        if self.assembly_only {
+            return self.output.clone().unwrap_or_else(|| {
+                Path::new(&self.input).with_extension("s").to_string_lossy().into_owned()
+            });
+        }
+
+        match self.output {
+            Some(_) => std::env::temp_dir()
+                .join(format!("crusty-{}.s", std::process::id()))
+                .to_string_lossy()
+                .into_owned(),
+            None => "out.s".to_string(),
+        }
+    }
+
+    /*
+        Arguments handed to gcc to assemble and link the generated assembly.
+    */
+    fn gcc_args(&self) -> Vec<String> {
+        let output = self.output.clone().unwrap_or_else(|| "out".to_string());
+        let mut args = vec![self.asm_path(), "-o".to_string(), output];
+
+        if self.debug_info {
+            args.push("-g".to_string());
+        }
+
+        for sanitizer in &self.sanitizers {
+            args.push(format!("-fsanitize={}", sanitizer));
+        }
+
+        /* Libraries have to come after the objects that reference them. */
+        args.extend(self.library_paths.iter().map(|path| format!("-L{}", path)));
+        args.extend(self.libraries.iter().map(|lib| format!("-l{}", lib)));
+        args.extend(self.link_args.iter().cloned());
+
+        args
+    }
+}
+
+fn preprocess(options: &Options, input: &str) -> Result<String, CrustyError> {
+    Preprocessor::new()
+        .with_file(&options.input)
+        .process(input)
+}
+
+/*
+    Runs the front end only, used by --check to report errors without generating any code.
+*/
+fn check(options: &Options, input: &str) -> Result<(), CrustyError> {
+    let source = preprocess(options, input)?;
+    let mut parser = Parser::new(Lexer::new(&source)).with_pedantic(options.pedantic);
+    let program = parser.parse()?;
+    typecheck::typecheck(&program)?;
+
+    Ok(())
+}
+
+/*
+    Prints the token stream of the preprocessed input, used by --emit-tokens to debug the lexer.
+*/
+fn emit_tokens(options: &Options, input: &str) -> Result<(), CrustyError> {
+    let source = preprocess(options, input)?;
+
+    for token in Lexer::new(&source).tokens()? {
+        let position = format!("{}:{}", token.line, token.column);
+        let token_type = format!("{:?}", token.token_type);
+        println!("{:>8}  {:<12} {:?}", position.dimmed(), token_type.bold().truecolor(88, 196, 252), token.lexeme);
+    }
+
+    Ok(())
+}
+
+/*
+    Prints the parse tree, used by --emit-ast to check how the input was parsed.
+*/
+fn emit_ast(options: &Options, input: &str) -> Result<(), CrustyError> {
+    let source = preprocess(options, input)?;
+    let program = Parser::new(Lexer::new(&source)).with_pedantic(options.pedantic).parse()?;
+
+    print!("{}", pretty::print_ast(&program));
+    Ok(())
+}
+
+/*
+    Reports a compile error from any stage and exits. This is the only place the error
+    prefix is printed, the errors themselves only format their location and message.
+*/
+fn fail(error: CrustyError) -> ! {
+    println!("{} {}", "[crusty::error]".bold().truecolor(252, 88, 88), error);
+    std::process::exit(1);
+}
 
 fn main() {
     let mut args: Vec<String> = std::env::args().collect();
@@ -16,25 +245,131 @@ fn main() {
     if args.len() > 1 {
         args.remove(0);
 
-        let file = args.join(" ");
-        let input = read_to_string(file.clone()).unwrap();
+        let options = match Options::parse(&args) {
+            Ok(options) => {
+                colored::control::set_override(options.color);
+                options
+            }
+            Err(e) => {
+                println!("{} {}", "[crusty]".bold().truecolor(252, 88, 88), e);
+                std::process::exit(1);
+            }
+        };
+
+        let input = read_to_string(&options.input).unwrap();
+
+        if options.emit_tokens {
+            if let Err(e) = emit_tokens(&options, &input) {
+                fail(e);
+            }
+
+            return;
+        }
+
+        if options.emit_ast {
+            if let Err(e) = emit_ast(&options, &input) {
+                fail(e);
+            }
+
+            return;
+        }
+
+        if options.check {
+            match check(&options, &input) {
+                Ok(()) => println!("{} No errors found.", "[crusty]".bold().truecolor(252, 88, 88)),
+                Err(e) => fail(e),
+            }
+
+            return;
+        }
+
+        let source = preprocess(&options, &input).unwrap_or_else(|e| fail(e));
+        let lexer = Lexer::new(&source);
+        let mut parser = Parser::new(lexer).with_pedantic(options.pedantic);
+        let mut codegen = CodeGen::new().with_syntax(options.syntax);
+        if options.debug_info {
+            codegen = codegen.with_debug_file(&options.input);
+        }
+        let mut program = parser.parse().unwrap_or_else(|e| fail(e));
 
-        let lexer = Lexer::new(&*input);
-        let mut parser = Parser::new(lexer);
-        let mut codegen = CodeGen::new();
-        let program = parser.parse().unwrap();
-        let asm = codegen.generate(&*program).unwrap();
+        if let Err(e) = typecheck::typecheck(&program) {
+            fail(e);
+        }
+
+        for warning in optimize::prune_unreachable(&mut program) {
+            println!("{} warning: {}", "[crusty]".bold().truecolor(252, 196, 88), warning);
+        }
+
+        if options.fold_constants && let Err(e) = optimize::fold_constants(&mut program) {
+            fail(e);
+        }
 
-        write("out.s", &asm).unwrap();
-        println!("{} Compiled!", "[crusty]".bold().truecolor(252, 88, 88));
+        if options.optimize_tail_strings {
+            optimize::merge_tail_strings(&mut program);
+        }
+
+        let asm = codegen.generate(&program).unwrap_or_else(|e| fail(e));
+
+        let asm_path = options.asm_path();
+        if asm_path == options.input {
+            println!("{} refusing to overwrite the input file {} with assembly", "[crusty]".bold().truecolor(252, 88, 88), asm_path);
+            std::process::exit(1);
+        }
+
+        write(&asm_path, &asm).unwrap();
+
+        if options.assembly_only {
+            println!("{} Wrote assembly to {}", "[crusty]".bold().truecolor(252, 88, 88), asm_path);
+        } else {
+            println!("{} Compiled!", "[crusty]".bold().truecolor(252, 88, 88));
+        }
+
+        if options.print_frame_sizes {
+            for (name, size) in codegen.frame_sizes() {
+                println!("{} frame size of '{}': {} bytes", "[crusty]".bold().truecolor(252, 88, 88), name, size);
+            }
+        }
+
+        if options.assembly_only {
+            return;
+        }
 
         let output = Command::new("gcc")
-            .args(["-no-pie", "out.s", "-o", "out"])
+            .args(options.gcc_args())
             .output()
             .expect("Failed to execute gcc");
 
+        if options.output.is_some() {
+            let _ = std::fs::remove_file(&asm_path);
+        }
+
         if !output.status.success() {
             println!("{} An error occurred while compiling: {}", "[crusty]".bold().truecolor(252, 88, 88), String::from_utf8_lossy(&output.stderr).bold().underline());
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Result<Options, String> {
+        Options::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn sanitizers_are_forwarded_to_gcc() {
+        let gcc_args = options(&["--sanitize", "address", "--sanitize", "undefined", "main.c", "-lm"]).unwrap().gcc_args();
+
+        let address = gcc_args.iter().position(|arg| arg == "-fsanitize=address").unwrap();
+        let undefined = gcc_args.iter().position(|arg| arg == "-fsanitize=undefined").unwrap();
+        let library = gcc_args.iter().position(|arg| arg == "-lm").unwrap();
+        assert!(address < undefined && undefined < library, "{:?}", gcc_args);
+    }
+
+    #[test]
+    fn unknown_sanitizer_is_rejected() {
+        let error = options(&["--sanitize", "thread", "main.c"]).err().unwrap();
+
+        assert_eq!(error, "unknown sanitizer 'thread', expected 'address' or 'undefined'");
+    }
+}
@@ -0,0 +1,269 @@
+use crate::ast::{Attribute, Binop, Expr, Stmt, UnaryOp};
+use crate::error::CrustyError;
+
+/* Functions whose constant format strings can safely be glued together. */
+const PRINT_FUNCTIONS: [&str; 1] = ["printf"];
+
+/* C library functions that never return to their caller. */
+const NORETURN_FUNCTIONS: [&str; 3] = ["exit", "_exit", "abort"];
+
+/*
+    Fuses adjacent print calls that only take a constant format string, e.g.
+        printf("a"); printf("b");
+    becomes
+        printf("ab");
+    Only directly adjacent statements are merged, so no other side effects can
+    happen in between. Strings containing '%' are left alone since gluing them
+    could change how the format is interpreted.
+*/
+pub fn merge_tail_strings(stmts: &mut Vec<Stmt>) {
+    let mut merged: Vec<Stmt> = Vec::with_capacity(stmts.len());
+
+    for stmt in stmts.drain(..) {
+        let stmt = match stmt {
+            Stmt::FunctionDecl(mut fdecl) => {
+                merge_tail_strings(&mut fdecl.body);
+                Stmt::FunctionDecl(fdecl)
+            }
+            Stmt::If(mut if_stmt) => {
+                merge_tail_strings(&mut if_stmt.then_body);
+                if let Some(else_body) = &mut if_stmt.else_body {
+                    merge_tail_strings(else_body);
+                }
+                Stmt::If(if_stmt)
+            }
+            Stmt::While(mut while_stmt) => {
+                merge_tail_strings(&mut while_stmt.body);
+                Stmt::While(while_stmt)
+            }
+            other => other,
+        };
+
+        if let Some((callee, text)) = constant_print(&stmt)
+            && let Some(Stmt::Expression(Expr::FunctionCall { callee: prev_callee, args }, _)) = merged.last_mut()
+            && prev_callee == callee
+            && let [Expr::String(prev_text)] = args.as_mut_slice()
+            && !prev_text.contains('%')
+        {
+            prev_text.push_str(text);
+            continue;
+        }
+
+        merged.push(stmt);
+    }
+
+    *stmts = merged;
+}
+
+/*
+    Returns the callee and string if the statement is a print call with a single constant string.
+*/
+fn constant_print(stmt: &Stmt) -> Option<(&str, &str)> {
+    match stmt {
+        Stmt::Expression(Expr::FunctionCall { callee, args }, _) if PRINT_FUNCTIONS.contains(&callee.as_str()) => {
+            match args.as_slice() {
+                [Expr::String(text)] if !text.contains('%') => Some((callee, text)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/*
+    Removes statements that can never run because they follow a `return`, or a call to
+    a function that doesn't come back (declared @noreturn, or exit/abort from libc).
+    Returns a warning for every function that had statements removed.
+*/
+pub fn prune_unreachable(stmts: &mut [Stmt]) -> Vec<String> {
+    let noreturn: Vec<String> = stmts.iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::FunctionDecl(fdecl) if fdecl.attributes.contains(&Attribute::NoReturn) => Some(fdecl.name.clone()),
+            _ => None,
+        })
+        .chain(NORETURN_FUNCTIONS.iter().map(|name| name.to_string()))
+        .collect();
+
+    let mut warnings = vec![];
+    for stmt in stmts.iter_mut() {
+        if let Stmt::FunctionDecl(fdecl) = stmt {
+            prune_body(&mut fdecl.body, &fdecl.name, &noreturn, &mut warnings);
+        }
+    }
+
+    warnings
+}
+
+fn prune_body(body: &mut Vec<Stmt>, fn_name: &str, noreturn: &[String], warnings: &mut Vec<String>) {
+    for stmt in body.iter_mut() {
+        match stmt {
+            Stmt::If(if_stmt) => {
+                prune_body(&mut if_stmt.then_body, fn_name, noreturn, warnings);
+                if let Some(else_body) = &mut if_stmt.else_body {
+                    prune_body(else_body, fn_name, noreturn, warnings);
+                }
+            }
+            Stmt::While(while_stmt) => prune_body(&mut while_stmt.body, fn_name, noreturn, warnings),
+            _ => {}
+        }
+    }
+
+    if let Some((idx, terminator)) = body.iter()
+        .enumerate()
+        .find_map(|(idx, stmt)| terminator(stmt, noreturn).map(|t| (idx, t)))
+        && idx + 1 < body.len()
+    {
+        let removed = body.len() - idx - 1;
+        body.truncate(idx + 1);
+        warnings.push(format!(
+            "{} unreachable statement(s) after the {} in '{}' removed",
+            removed, terminator, fn_name
+        ));
+    }
+}
+
+/*
+    Describes the statement if control never continues past it.
+*/
+fn terminator(stmt: &Stmt, noreturn: &[String]) -> Option<String> {
+    match stmt {
+        Stmt::Return(_) => Some("return".to_string()),
+        Stmt::Expression(Expr::FunctionCall { callee, .. }, _) if noreturn.contains(callee) => {
+            Some(format!("call to '{}'", callee))
+        }
+        _ => None,
+    }
+}
+
+/*
+    Evaluates arithmetic on literals at compile time, e.g. `2 * 3 + 4` becomes `10`.
+    Values are folded as 32 bit ints like the generated code would compute them, unless
+    a literal is too wide for an int and the math is done in 64 bits. A constant division
+    by zero is reported instead of being left to trap at runtime.
+*/
+pub fn fold_constants(stmts: &mut [Stmt]) -> Result<(), CrustyError> {
+    for stmt in stmts.iter_mut() {
+        let line = stmt.line();
+        let fold = |expr: &mut Expr| fold_expr(expr).map_err(|msg| CrustyError::Codegen { msg, file: String::new(), line });
+
+        match stmt {
+            Stmt::Expression(expr, _) => fold(expr)?,
+            Stmt::VariableDecl(vdecl) => fold(&mut vdecl.value)?,
+            Stmt::MultiDecl(vdecls) => {
+                for vdecl in vdecls.iter_mut() {
+                    fold(&mut vdecl.value)?;
+                }
+            }
+            Stmt::Return(ret) => fold(&mut ret.value)?,
+            Stmt::Assign(assign) => {
+                if let Some(index) = &mut assign.index {
+                    fold(index)?;
+                }
+                fold(&mut assign.value)?;
+            }
+            /* statements only know their line, the file comes from the function they're in */
+            Stmt::FunctionDecl(fdecl) => fold_constants(&mut fdecl.body).map_err(|e| match e {
+                CrustyError::Codegen { msg, line, .. } => CrustyError::Codegen { msg, file: fdecl.file.clone(), line },
+                e => e,
+            })?,
+            Stmt::If(if_stmt) => {
+                fold(&mut if_stmt.cond)?;
+                fold_constants(&mut if_stmt.then_body)?;
+                if let Some(else_body) = &mut if_stmt.else_body {
+                    fold_constants(else_body)?;
+                }
+            }
+            Stmt::While(while_stmt) => {
+                fold(&mut while_stmt.cond)?;
+                fold_constants(&mut while_stmt.body)?;
+            }
+            Stmt::ExternDecl(_) | Stmt::ArrayDecl(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/*
+    Folds the operands first, so nested constant expressions collapse bottom up.
+*/
+fn fold_expr(expr: &mut Expr) -> Result<(), String> {
+    let folded = match expr {
+        Expr::BinaryOp { left, op, right } => {
+            fold_expr(left)?;
+            fold_expr(right)?;
+
+            match (left.as_ref(), right.as_ref()) {
+                (Expr::Int(l), Expr::Int(r)) => {
+                    let value = fold_binary(*l, op, *r)?;
+                    Some(if fits_int(*l) && fits_int(*r) { value as i32 as i64 } else { value })
+                }
+                _ => None,
+            }
+        }
+        Expr::Unary { op, operand } => {
+            fold_expr(operand)?;
+
+            match (op, operand.as_ref()) {
+                (UnaryOp::Neg, Expr::Int(n)) if fits_int(*n) => Some((*n as i32).wrapping_neg() as i64),
+                (UnaryOp::Neg, Expr::Int(n)) => Some(n.wrapping_neg()),
+                (UnaryOp::Not, Expr::Int(n)) => Some((*n == 0) as i64),
+                _ => None,
+            }
+        }
+        Expr::FunctionCall { args, .. } => {
+            for arg in args.iter_mut() {
+                fold_expr(arg)?;
+            }
+
+            None
+        }
+        Expr::Index { index, .. } => {
+            fold_expr(index)?;
+            None
+        }
+        Expr::Deref(pointer) => {
+            fold_expr(pointer)?;
+            None
+        }
+        Expr::Comma(exprs) => {
+            for expr in exprs.iter_mut() {
+                fold_expr(expr)?;
+            }
+
+            None
+        }
+        _ => None,
+    };
+
+    if let Some(value) = folded {
+        *expr = Expr::Int(value);
+    }
+
+    Ok(())
+}
+
+fn fits_int(n: i64) -> bool {
+    i32::try_from(n).is_ok()
+}
+
+fn fold_binary(l: i64, op: &Binop, r: i64) -> Result<i64, String> {
+    let value = match op {
+        Binop::Add => l.wrapping_add(r),
+        Binop::Sub => l.wrapping_sub(r),
+        Binop::Mul => l.wrapping_mul(r),
+        Binop::Div | Binop::Mod if r == 0 => return Err("division by zero in constant expression".to_string()),
+        Binop::Div => l.wrapping_div(r),
+        Binop::Mod => l.wrapping_rem(r),
+        Binop::Eq => (l == r) as i64,
+        Binop::Ne => (l != r) as i64,
+        Binop::Lt => (l < r) as i64,
+        Binop::Gt => (l > r) as i64,
+        Binop::Le => (l <= r) as i64,
+        Binop::Ge => (l >= r) as i64,
+        Binop::And => (l != 0 && r != 0) as i64,
+        Binop::Or => (l != 0 || r != 0) as i64,
+    };
+
+    Ok(value)
+}
@@ -1,42 +1,79 @@
-use crate::ast::{Binop, Expr, FunctionDecl, Parameter, Return, Stmt, VariableDecl};
-use crate::ast::Expr::{FunctionCall, Identifier};
+use crate::ast::{ArrayDecl, Assign, Attribute, Binop, Expr, ExternDecl, FunctionDecl, If, Parameter, Return, Stmt, UnaryOp, VariableDecl, While};
+use crate::ast::Expr::FunctionCall;
+use crate::error::CrustyError;
 use crate::lexer::*;
 
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     current: Option<Token>,
+    next: Option<Result<Token, CrustyError>>,   /* the token after current, once peek_next lexed it */
+    lex_error: Option<CrustyError>,   /* ends the token stream, reported instead of a parse error */
+    pedantic: bool,     /* reject implicit auto and chained comparisons */
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(mut lexer: Lexer<'a>) -> Self {
-        let current = lexer.next();
-        Self { lexer, current }
+    pub fn new(lexer: Lexer<'a>) -> Self {
+        let mut parser = Self { lexer, current: None, next: None, lex_error: None, pedantic: false };
+        parser.advance();
+        parser
+    }
+
+    pub fn with_pedantic(mut self, pedantic: bool) -> Self {
+        self.pedantic = pedantic;
+        self
     }
 
     fn advance(&mut self) {
-        self.current = self.lexer.next();
+        if self.lex_error.is_some() {
+            return;
+        }
+
+        let next = self.next.take().unwrap_or_else(|| self.lexer.next_token());
+        match next {
+            Ok(token) => self.current = Some(token),
+            Err(e) => {
+                self.current = None;
+                self.lex_error = Some(e);
+            }
+        }
     }
 
     fn check(&self, target_type: &TokenType) -> bool {
         if let Some(ref token) = self.current {
             token.token_type == *target_type
         } else {
-            *target_type == TokenType::EOF
+            *target_type == TokenType::Eof
         }
     }
 
-    fn consume(&mut self, expected: TokenType) -> Result<Token, String> {
+    fn consume(&mut self, expected: TokenType) -> Result<Token, CrustyError> {
         if self.check(&expected) {
             let token = self.current.clone();
             self.advance();
 
-            token.ok_or_else(|| "[twee::error] unexpected end of input".to_string())
+            token.ok_or_else(|| self.error("unexpected end of input"))
         } else {
-            Err(format!(
-                "[twee::error] expected {:?} but got {:?}",
-                expected,
-                self.current.as_ref().map(|t| &t.token_type)
-            ))
+            let got = match &self.current {
+                Some(token) => format!("{:?}", token.token_type),
+                None => "end of input".to_string(),
+            };
+
+            Err(self.error(&format!("expected {:?} but got {}", expected, got)))
+        }
+    }
+
+    /*
+        A diagnostic at the position of the current token.
+    */
+    fn error(&self, msg: &str) -> CrustyError {
+        /* whatever the parser expected, the real problem is the input it couldn't lex */
+        if let Some(e) = &self.lex_error {
+            return e.clone();
+        }
+
+        match &self.current {
+            Some(token) => CrustyError::Parse { msg: msg.to_string(), file: token.file.clone(), line: token.line, col: token.column },
+            None => CrustyError::Parse { msg: msg.to_string(), file: String::new(), line: 0, col: 0 },
         }
     }
 
@@ -44,27 +81,58 @@ impl<'a> Parser<'a> {
         self.current.as_ref()
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, String> {
+    /*
+        Returns the token after the current one without consuming anything. It's lexed
+        once and kept until advance moves onto it.
+    */
+    fn peek_next(&mut self) -> Option<&Token> {
+        if self.next.is_none() {
+            self.next = Some(self.lexer.next_token());
+        }
+
+        self.next.as_ref()?.as_ref().ok()
+    }
+
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, CrustyError> {
         let mut stmts = Vec::new();
 
-        while !self.check(&TokenType::EOF) {
+        while !self.check(&TokenType::Eof) {
             stmts.push(self.parse_stmt()?);
             if self.check(&TokenType::Semi) {
                 self.advance();
             }
         }
 
+        /* the lexer stopping early looks like the end of the input */
+        if let Some(e) = self.lex_error.take() {
+            return Err(e);
+        }
+
         Ok(stmts)
     }
 
-    fn parse_stmt(&mut self) -> Result<Stmt, String> {
+    fn parse_stmt(&mut self) -> Result<Stmt, CrustyError> {
+        let assignment = self.check(&TokenType::Identifier)
+            && self.peek_next().is_some_and(|t| Self::is_assign_op(&t.token_type) || t.token_type == TokenType::LBracket);
+
         let stmt = match self.peek() {
             Some(token) => match token.token_type {
                 TokenType::DataType => self.parse_variable_declaration()?,
                 TokenType::Return => self.parse_return_stmt()?,
-                _ => Stmt::Expression(self.parse_expr()?),
+                TokenType::If => self.parse_if_stmt()?,
+                TokenType::While => self.parse_while_stmt()?,
+                TokenType::Identifier if assignment => self.parse_assignment()?,
+                TokenType::Mul => self.parse_assignment()?,
+                TokenType::Extern => self.parse_extern_declaration()?,
+                TokenType::Static => self.parse_static_declaration()?,
+                TokenType::At => self.parse_attributed_declaration()?,
+                TokenType::Register => self.parse_register_declaration()?,
+                _ => {
+                    let line = token.line;
+                    Stmt::Expression(self.parse_expr()?, line)
+                }
             },
-            None => return Err("[twee::error] unexpected end of input".to_string()),
+            None => return Err(self.error("unexpected end of input")),
         };
 
         if self.check(&TokenType::Semi) {
@@ -81,27 +149,283 @@ impl<'a> Parser<'a> {
         Example:
             return 42;
      */
-    fn parse_return_stmt(&mut self) -> Result<Stmt, String> {
-        self.consume(TokenType::Return)?;
+    fn parse_return_stmt(&mut self) -> Result<Stmt, CrustyError> {
+        let line = self.consume(TokenType::Return)?.line;
         let value = self.parse_expr()?;
-        Ok(Stmt::Return(Return { value }))
+        Ok(Stmt::Return(Return { value, line }))
+    }
+
+    /*
+        Parse an if statement with an optional else branch.
+        Syntax:
+            if (cond<Expr>) { ... } else { ... }
+        Example:
+            if (x < 10) { return 1; } else if (x < 20) { return 2; } else { return 3; }
+    */
+    fn parse_if_stmt(&mut self) -> Result<Stmt, CrustyError> {
+        let line = self.consume(TokenType::If)?.line;
+        self.consume(TokenType::LParen)?;
+        let cond = self.parse_expr()?;
+        self.consume(TokenType::RParen)?;
+
+        let then_body = self.parse_block()?;
+
+        let else_body = if self.check(&TokenType::Else) {
+            self.advance();
+
+            if self.check(&TokenType::If) {
+                Some(vec![self.parse_if_stmt()?])
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
+
+        Ok(Stmt::If(If { cond, then_body, else_body, line }))
+    }
+
+    /*
+        Parse an assignment to an existing variable, compound assignments are
+        desugared so `x += 5` becomes `x = x + 5`.
+        Syntax:
+            ident = value<Expr>;
+            ident op= value<Expr>;
+            ident[index<Expr>] = value<Expr>;
+            *ident = value<Expr>;
+        Example:
+            count = count + 1;
+            count += 1;
+            arr[i] = 0;
+            *p = 5;
+    */
+    fn parse_assignment(&mut self) -> Result<Stmt, CrustyError> {
+        let deref = self.check(&TokenType::Mul);
+        if deref {
+            self.advance();
+        }
+
+        let Token { lexeme: name, line, column, .. } = self.consume(TokenType::Identifier)?;
+        let index = if self.check(&TokenType::LBracket) { Some(self.parse_index()?) } else { None };
+
+        let op = match self.peek().map(|t| &t.token_type) {
+            Some(TokenType::PlusEq) => Some(Binop::Add),
+            Some(TokenType::MinusEq) => Some(Binop::Sub),
+            Some(TokenType::StarEq) => Some(Binop::Mul),
+            Some(TokenType::SlashEq) => Some(Binop::Div),
+            _ => None,
+        };
+
+        if op.is_some() {
+            self.advance();
+        } else {
+            self.consume(TokenType::Equals)?;
+        }
+
+        let mut value = self.parse_expr()?;
+        if let Some(op) = op {
+            let target = Expr::Identifier { name: name.clone(), line, column };
+            let left = match &index {
+                Some(index) => Expr::Index { base: Box::new(target), index: Box::new(index.clone()) },
+                None if deref => Expr::Deref(Box::new(target)),
+                None => target,
+            };
+
+            value = Expr::BinaryOp {
+                left: Box::new(left),
+                op,
+                right: Box::new(value),
+            };
+        }
+
+        Ok(Stmt::Assign(Assign { name, index, deref, value, line }))
+    }
+
+    /*
+        Parse the index of an array element.
+        Syntax:
+            [index<Expr>]
+    */
+    fn parse_index(&mut self) -> Result<Expr, CrustyError> {
+        self.consume(TokenType::LBracket)?;
+        let index = self.parse_expr()?;
+        self.consume(TokenType::RBracket)?;
+        Ok(index)
+    }
+
+    fn is_assign_op(token_type: &TokenType) -> bool {
+        matches!(token_type, TokenType::Equals | TokenType::PlusEq | TokenType::MinusEq | TokenType::StarEq | TokenType::SlashEq)
+    }
+
+    /*
+        Parse a while loop.
+        Syntax:
+            while (cond<Expr>) { ... }
+        Example:
+            while (i < 10) { i = i + 1; }
+    */
+    fn parse_while_stmt(&mut self) -> Result<Stmt, CrustyError> {
+        let line = self.consume(TokenType::While)?.line;
+        self.consume(TokenType::LParen)?;
+        let cond = self.parse_expr()?;
+        self.consume(TokenType::RParen)?;
+
+        let body = self.parse_block()?;
+        Ok(Stmt::While(While { cond, body, line }))
+    }
+
+    /*
+        Parse the statements between a pair of braces.
+    */
+    fn parse_block(&mut self) -> Result<Vec<Stmt>, CrustyError> {
+        let mut body = vec![];
+        self.consume(TokenType::LBrace)?;
+
+        while !self.check(&TokenType::RBrace) {
+            body.push(self.parse_stmt()?);
+        }
+
+        self.consume(TokenType::RBrace)?;
+        Ok(body)
+    }
+
+    /*
+        Parse an extern global variable declaration or function prototype, parameter
+        names in a prototype are optional.
+        Syntax:
+            extern data_type<Ident> ident;
+            extern data_type<Ident> ident(params);
+        Example:
+            extern int counter;
+            extern double pow(double, double);
+    */
+    fn parse_extern_declaration(&mut self) -> Result<Stmt, CrustyError> {
+        let line = self.consume(TokenType::Extern)?.line;
+        let data_type = self.parse_data_type()?;
+        let name = self.consume(TokenType::Identifier)?.lexeme;
+
+        let params = if self.check(&TokenType::LParen) {
+            Some(self.parse_params(true)?)
+        } else {
+            None
+        };
+
+        Ok(Stmt::ExternDecl(ExternDecl { data_type, name, line, params }))
+    }
+
+    /*
+        Parse a file-local function declaration.
+        Syntax:
+            static data_type<Ident> ident(params) { ... }
+        Example:
+            static int helper() { return 1; }
+    */
+    fn parse_static_declaration(&mut self) -> Result<Stmt, CrustyError> {
+        self.consume(TokenType::Static)?;
+
+        match self.parse_variable_declaration()? {
+            Stmt::FunctionDecl(mut fdecl) => {
+                if fdecl.name == "main" {
+                    return Err(self.error("'main' cannot be declared static"));
+                }
+
+                fdecl.is_static = true;
+                Ok(Stmt::FunctionDecl(fdecl))
+            }
+            _ => Err(self.error("'static' is only supported on function declarations")),
+        }
+    }
+
+    /*
+        Parse a variable declaration hinted to live in a register.
+        Syntax:
+            register data_type<Ident> ident = value<Expr>;
+        Example:
+            register int i = 0;
+    */
+    fn parse_register_declaration(&mut self) -> Result<Stmt, CrustyError> {
+        self.consume(TokenType::Register)?;
+
+        /* the implicit 'auto' type has no size, a variable needs a real one */
+        if !self.check(&TokenType::DataType) {
+            return Err(self.error("'register' needs a data type, e.g. register int x = 5"));
+        }
+
+        match self.parse_variable_declaration()? {
+            Stmt::VariableDecl(mut vdecl) => {
+                vdecl.register = true;
+                Ok(Stmt::VariableDecl(vdecl))
+            }
+            Stmt::MultiDecl(mut vdecls) => {
+                for vdecl in vdecls.iter_mut() {
+                    vdecl.register = true;
+                }
+                Ok(Stmt::MultiDecl(vdecls))
+            }
+            _ => Err(self.error("'register' can only be applied to variable declarations")),
+        }
+    }
+
+    /*
+        Parse attributes followed by the function declaration they describe.
+        Syntax:
+            @attribute<Ident> ... function_declaration
+        Example:
+            @noreturn @cold int fail() { exit(1); }
+    */
+    fn parse_attributed_declaration(&mut self) -> Result<Stmt, CrustyError> {
+        let mut attributes = vec![];
+
+        while self.check(&TokenType::At) {
+            self.advance();
+            let name = self.consume(TokenType::Identifier)?.lexeme;
+
+            match Attribute::parse(&name) {
+                Some(attribute) => attributes.push(attribute),
+                None => return Err(self.error(&format!("unknown attribute '@{}'", name))),
+            }
+        }
+
+        let stmt = if self.check(&TokenType::Static) {
+            self.parse_static_declaration()?
+        } else {
+            self.parse_variable_declaration()?
+        };
+
+        match stmt {
+            Stmt::FunctionDecl(mut fdecl) => {
+                fdecl.attributes = attributes;
+                Ok(Stmt::FunctionDecl(fdecl))
+            }
+            _ => Err(self.error("attributes can only be applied to function declarations")),
+        }
     }
 
     /*
-        Parse a local variable declaration.
+        Parse a local variable declaration, more variables can follow after commas and
+        share the leading data type.
         Syntax:
-            data_type<Ident> ident = value<Expr>;<Optional>
+            data_type<Ident> ident = value<Expr>, ident = value<Expr> ...;<Optional>
         Example:
             int number = 24;
+            int a = 1, b = 2, c = 3;
     */
-    fn parse_variable_declaration(&mut self) -> Result<Stmt, String> {
+    fn parse_variable_declaration(&mut self) -> Result<Stmt, CrustyError> {
+        let line = self.peek().map_or(0, |token| token.line);
+
         /* Expect a data type token */
         let data_type = if self.check(&TokenType::DataType) {
-            // self.advance();
-
-            let data_type_str = self.consume(TokenType::DataType)?.lexeme;
-            data_type_str
+            self.parse_data_type()?
+        } else if self.pedantic {
+            return Err(self.error("missing data type, the implicit 'auto' is rejected by --pedantic"));
         } else {
+            /*
+                Only reachable through `static` or an attribute, a statement starting with an
+                identifier is an assignment or expression. Both only accept functions, so this
+                is a function without a return type, as in `static helper() { ... }`. Its type
+                isn't inferred from its returns, the value is returned as an int like C's
+                implicit int.
+            */
             "auto".to_string()
         };
 
@@ -109,7 +433,11 @@ impl<'a> Parser<'a> {
         let name = self.consume(TokenType::Identifier)?.lexeme;
 
         if self.check(&TokenType::LParen) {
-            return self.parse_function_declaration(data_type, name);
+            return self.parse_function_declaration(data_type, name, line);
+        }
+
+        if self.check(&TokenType::LBracket) {
+            return self.parse_array_declaration(data_type, name, line);
         }
 
         /* Expect and consume an equals symbol. */
@@ -118,19 +446,98 @@ impl<'a> Parser<'a> {
         /* Parse an expression for the value of the variable. */
         let value = self.parse_expr()?;
 
-        Ok(Stmt::VariableDecl(VariableDecl {
+        let vdecl = VariableDecl { data_type, name, value, line, register: false };
+        if !self.check(&TokenType::Comma) {
+            return Ok(Stmt::VariableDecl(vdecl));
+        }
+
+        let mut vdecls = vec![vdecl];
+        while self.check(&TokenType::Comma) {
+            self.advance();
+
+            let name = self.consume(TokenType::Identifier)?.lexeme;
+            self.consume(TokenType::Equals)?;
+            let value = self.parse_expr()?;
+
+            vdecls.push(VariableDecl { data_type: vdecls[0].data_type.clone(), name, value, line, register: false });
+        }
+
+        Ok(Stmt::MultiDecl(vdecls))
+    }
+
+    /*
+        Parse a type name, a '*' written apart from the base type still makes it a pointer.
+        Syntax:
+            data_type<DataType> *...
+        Example:
+            char *name
+    */
+    fn parse_data_type(&mut self) -> Result<String, CrustyError> {
+        let mut data_type = self.consume(TokenType::DataType)?.lexeme;
+
+        while self.check(&TokenType::Mul) {
+            self.advance();
+            data_type.push('*');
+        }
+
+        Ok(data_type)
+    }
+
+    /*
+        Parse a fixed size array declaration, the size has to be a literal.
+        Syntax:
+            data_type<Ident> ident[size<Number>];
+        Example:
+            int arr[5];
+    */
+    fn parse_array_declaration(&mut self, data_type: String, name: String, line: usize) -> Result<Stmt, CrustyError> {
+        self.consume(TokenType::LBracket)?;
+
+        let size_token = self.consume(TokenType::Number)?;
+        let size = Self::parse_int(&size_token.lexeme).map_err(|e| self.error(&e))?;
+        if size <= 0 {
+            return Err(CrustyError::Parse {
+                msg: format!("array '{}' must have a positive size", name),
+                file: size_token.file,
+                line: size_token.line,
+                col: size_token.column,
+            });
+        }
+
+        self.consume(TokenType::RBracket)?;
+        Ok(Stmt::ArrayDecl(ArrayDecl { data_type, name, size: size as usize, line }))
+    }
+
+    /*
+        Parse a function declaration.
+        Syntax:
+            int main() { ... }
+    */
+    fn parse_function_declaration(&mut self, data_type: String, name: String, line: usize) -> Result<Stmt, CrustyError> {
+        let file = self.peek().map_or(String::new(), |token| token.file.clone());
+        let params = self.parse_params(false)?;
+        let body = self.parse_block()?;
+
+        Ok(Stmt::FunctionDecl(FunctionDecl {
             data_type,
             name,
-            value,
+            body,
+            params,
+            is_static: false,
+            attributes: vec![],
+            line,
+            file,
         }))
     }
 
     /*
-        Parse a function declaration.
+        Parse a parenthesized parameter list, a prototype may leave the names out.
         Syntax:
-            int main() { ... }
+            (data_type<Ident> restrict<Optional> ident, ...)
+        Example:
+            (char* restrict dst, int n)
     */
-    fn parse_function_declaration(&mut self, data_type: String, name: String) -> Result<Stmt, String> {
+    fn parse_params(&mut self, prototype: bool) -> Result<Vec<Parameter>, CrustyError> {
         self.consume(TokenType::LParen)?;
 
         let mut params: Vec<Parameter> = vec![];
@@ -140,12 +547,28 @@ impl<'a> Parser<'a> {
             self.advance();
         } else {
             loop {
-                let param_type = self.consume(TokenType::DataType)?.lexeme;
-                let param_name = self.consume(TokenType::Identifier)?.lexeme;
+                let param_type = self.parse_data_type()?;
+
+                /* restrict is only meaningful on pointers */
+                let restrict = self.check(&TokenType::Restrict);
+                if restrict {
+                    self.advance();
+
+                    if !param_type.ends_with('*') {
+                        return Err(self.error(&format!("'restrict' can only qualify pointer types, not '{}'", param_type)));
+                    }
+                }
+
+                let param_name = if prototype && !self.check(&TokenType::Identifier) {
+                    String::new()
+                } else {
+                    self.consume(TokenType::Identifier)?.lexeme
+                };
 
                 params.push(Parameter {
                     data_type: param_type,
-                    name: param_name
+                    name: param_name,
+                    restrict,
                 });
 
                 if self.check(&TokenType::Comma) {
@@ -154,38 +577,29 @@ impl<'a> Parser<'a> {
                     self.advance(); // eat da ')'
                     break;
                 } else {
-                    return Err("Expected ',' or ')' in function parameters".to_string());
+                    return Err(self.error("expected ',' or ')' in function parameters"));
                 }
             }
         }
 
-        let mut body = vec![];
-        self.consume(TokenType::LBrace)?;
-
-        loop {
-            if self.check(&TokenType::RBrace) { break; }
-            body.push(self.parse_stmt()?);
-        }
-
-        self.consume(TokenType::RBrace)?;
-
-        Ok(Stmt::FunctionDecl(FunctionDecl {
-            data_type,
-            name,
-            body,
-            params
-        }))
+        Ok(params)
     }
 
     /*
         Parse an ordinary expression.
     */
-    fn parse_expr(&mut self) -> Result<Expr, String> {
-        self.parse_precedence(0) /* Start with parsing by preceden */
+    fn parse_expr(&mut self) -> Result<Expr, CrustyError> {
+        /* Start with parsing by preceden */
+        self.parse_precedence(0).map(|(expr, _)| expr)
     }
 
-    fn parse_precedence(&mut self, min: u8) -> Result<Expr, String> {
-        let mut left = self.parse_primary()?;
+    /*
+        Also returns whether the expression is a comparison that wasn't parenthesized,
+        so --pedantic can reject chains like a < b < c that compare a 0/1 result.
+    */
+    fn parse_precedence(&mut self, min: u8) -> Result<(Expr, bool), CrustyError> {
+        let mut left = self.parse_unary()?;
+        let mut left_compares = false;
 
         while let Some(op) = self.binop() {
             let precedence = op.precedence();
@@ -202,8 +616,19 @@ impl<'a> Parser<'a> {
                 precedence
             };
 
-            let right = self.parse_precedence(right_min)?;
+            let (right, right_compares) = self.parse_precedence(right_min)?;
 
+            if self.pedantic && op.is_comparison() && (left_compares || right_compares) {
+                return Err(self.error("chained comparison, a < b < c compares the 0/1 result of a < b with c and is rejected by --pedantic"));
+            }
+
+            /* "a" + "b" is joined here like adjacent literals, there's no runtime string concatenation */
+            if let (Expr::String(l), Binop::Add, Expr::String(r)) = (&mut left, &op, &right) {
+                l.push_str(r);
+                continue;
+            }
+
+            left_compares = op.is_comparison();
             left = Expr::BinaryOp {
                 left: Box::new(left),
                 op,
@@ -211,62 +636,144 @@ impl<'a> Parser<'a> {
             };
         }
 
-        Ok(left)
+        Ok((left, left_compares))
+    }
+
+    /*
+        Parse prefix operators, they bind tighter than any binary operator so
+        -5 + 3 is (-5) + 3.
+    */
+    fn parse_unary(&mut self) -> Result<Expr, CrustyError> {
+        let op = match self.peek().map(|t| &t.token_type) {
+            Some(TokenType::Sub) => UnaryOp::Neg,
+            Some(TokenType::Bang) => UnaryOp::Not,
+            /* prefix '*' and '&', in infix position they never get here */
+            Some(TokenType::Mul) => {
+                self.advance();
+                return Ok(Expr::Deref(Box::new(self.parse_unary()?)));
+            }
+            Some(TokenType::Amp) => {
+                self.advance();
+                return Ok(Expr::AddrOf(Box::new(self.parse_unary()?)));
+            }
+            _ => return self.parse_primary(),
+        };
+
+        self.advance();
+        let operand = self.parse_unary()?;
+
+        Ok(Expr::Unary { op, operand: Box::new(operand) })
     }
 
     /*
         Parse primary expressions (literals, identifiers, and grouped expressions).
     */
-    fn parse_primary(&mut self) -> Result<Expr, String> {
+    fn parse_primary(&mut self) -> Result<Expr, CrustyError> {
         match self.peek() {
             Some(token) => match token.token_type.clone() {
                 /* Parse a numeric literal. */
                 TokenType::Number => {
-                    let value = token.lexeme.parse::<f64>().map_err(|e| e.to_string())?;
+                    let value = Self::parse_int(&token.lexeme).map_err(|e| self.error(&e))?;
+                    self.advance();
+
+                    Ok(Expr::Int(value))
+                }
+
+                TokenType::Float => {
+                    let value = token.lexeme.parse::<f64>().map_err(|e| self.error(&e.to_string()))?;
+                    self.advance();
+
+                    Ok(Expr::Float(value))
+                }
+
+                /* true and false are just the ints 1 and 0 */
+                TokenType::True | TokenType::False => {
+                    let value = (token.token_type == TokenType::True) as i64;
                     self.advance();
 
-                    Ok(Expr::Number(value))
+                    Ok(Expr::Int(value))
                 }
 
                 /* Parse a reference to an identifier */
                 TokenType::Identifier => {
-                    let value = token.lexeme.clone();
+                    let (value, line, column) = (token.lexeme.clone(), token.line, token.column);
                     self.advance();
 
                     if self.check(&TokenType::LParen) {
-                        return Ok(self.parse_function_call(value)?)
+                        return self.parse_function_call(value)
                     }
 
-                    Ok(Expr::Identifier(value))
+                    let identifier = Expr::Identifier { name: value, line, column };
+                    if self.check(&TokenType::LBracket) {
+                        let index = self.parse_index()?;
+                        return Ok(Expr::Index { base: Box::new(identifier), index: Box::new(index) });
+                    }
+
+                    Ok(identifier)
                 }
 
-                /* Parse a string literal. */
+                /* Parse a string literal, adjacent literals are folded into one like in C. */
                 TokenType::String => {
-                    let value = token.lexeme.clone();
+                    let mut value = token.lexeme.clone();
                     self.advance();
 
+                    while let Some(next) = self.peek().filter(|t| t.token_type == TokenType::String) {
+                        value.push_str(&next.lexeme);
+                        self.advance();
+                    }
+
                     Ok(Expr::String(value))
                 }
 
-                /* Parse parenthesized expressions */
+                /* Parse a character literal, stored as its byte value. */
+                TokenType::Char => {
+                    let value = token.lexeme.chars().next().unwrap_or_default();
+                    if !value.is_ascii() {
+                        return Err(self.error(&format!("character literal '{}' doesn't fit in a char", value)));
+                    }
+
+                    self.advance();
+                    Ok(Expr::Char(value as u8))
+                }
+
+                /* Parse parenthesized expressions, a comma inside makes it a sequence */
                 TokenType::LParen => {
                     self.advance(); // consume '('
-                    let expr = self.parse_expr()?;
+                    let mut exprs = vec![self.parse_expr()?];
+                    while self.check(&TokenType::Comma) {
+                        self.advance();
+                        exprs.push(self.parse_expr()?);
+                    }
                     self.consume(TokenType::RParen)?; // consume ')'
-                    Ok(expr)
+
+                    match exprs.len() {
+                        1 => Ok(exprs.remove(0)),
+                        _ => Ok(Expr::Comma(exprs)),
+                    }
                 }
 
-                _ => Err(format!(
-                    "[twee::error] unexpected token {:?}",
-                    token.token_type
-                )),
+                _ => Err(self.error(&format!("unexpected token {:?}", token.token_type))),
             },
 
-            None => Err("[twee::error] unexpected end of input".to_string()),
+            None => Err(self.error("unexpected end of input")),
         }
     }
 
-    fn parse_function_call(&mut self, callee: String) -> Result<Expr, String> {
+    /*
+        Converts an integer literal, the lexer keeps any 0x/0b/0o prefix in the lexeme.
+    */
+    fn parse_int(lexeme: &str) -> Result<i64, String> {
+        let (digits, radix) = match lexeme.get(..2).map(|prefix| prefix.to_ascii_lowercase()) {
+            Some(prefix) if prefix == "0x" => (&lexeme[2..], 16),
+            Some(prefix) if prefix == "0b" => (&lexeme[2..], 2),
+            Some(prefix) if prefix == "0o" => (&lexeme[2..], 8),
+            _ => (lexeme, 10),
+        };
+
+        i64::from_str_radix(digits, radix).map_err(|e| format!("invalid literal '{}': {}", lexeme, e))
+    }
+
+    fn parse_function_call(&mut self, callee: String) -> Result<Expr, CrustyError> {
         self.advance();
 
         let mut args: Vec<Expr> = vec![];
@@ -285,7 +792,7 @@ impl<'a> Parser<'a> {
             } else if self.check(&TokenType::RParen) {
                 break;
             } else {
-                return Err("Expected ',' or ')' in function call arguments list.".to_string());
+                return Err(self.error("expected ',' or ')' in function call arguments"));
             }
         }
 
@@ -298,7 +805,7 @@ impl<'a> Parser<'a> {
     }
 
     /*
-        Is the current token a binary operator? (add, sub, mul, div) if so return it as a binop.
+        Is the current token a binary operator? (arithmetic, comparison or logical) if so return it as a binop.
     */
     fn binop(&self) -> Option<Binop> {
         match self.peek() {
@@ -307,6 +814,15 @@ impl<'a> Parser<'a> {
                 TokenType::Sub => Some(Binop::Sub),
                 TokenType::Mul => Some(Binop::Mul),
                 TokenType::Div => Some(Binop::Div),
+                TokenType::Mod => Some(Binop::Mod),
+                TokenType::EqEq => Some(Binop::Eq),
+                TokenType::NotEq => Some(Binop::Ne),
+                TokenType::Lt => Some(Binop::Lt),
+                TokenType::Gt => Some(Binop::Gt),
+                TokenType::LtEq => Some(Binop::Le),
+                TokenType::GtEq => Some(Binop::Ge),
+                TokenType::AndAnd => Some(Binop::And),
+                TokenType::OrOr => Some(Binop::Or),
                 _ => None,
             },
 
@@ -314,3 +830,40 @@ impl<'a> Parser<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Vec<Stmt>, CrustyError> {
+        Parser::new(Lexer::new(source)).parse()
+    }
+
+    #[test]
+    fn restrict_qualifies_pointer_parameters() {
+        let stmts = parse("int fill(int* restrict p, int n) { return n; }").unwrap();
+        let Stmt::FunctionDecl(fdecl) = &stmts[0] else {
+            panic!("expected a function, got {:?}", stmts[0]);
+        };
+
+        assert!(fdecl.params[0].restrict);
+        assert!(!fdecl.params[1].restrict);
+    }
+
+    #[test]
+    fn restrict_is_rejected_on_non_pointer_types() {
+        let error = parse("int f(int restrict x) { return x; }").unwrap_err();
+
+        assert!(error.to_string().contains("'restrict' can only qualify pointer types, not 'int'"), "{}", error);
+    }
+
+    #[test]
+    fn attributes_are_recorded_on_the_function() {
+        let stmts = parse("@noreturn @cold int fail() { exit(1); }").unwrap();
+        let Stmt::FunctionDecl(fdecl) = &stmts[0] else {
+            panic!("expected a function, got {:?}", stmts[0]);
+        };
+
+        assert_eq!(fdecl.attributes, [Attribute::NoReturn, Attribute::Cold]);
+    }
+}
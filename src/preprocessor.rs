@@ -0,0 +1,741 @@
+use std::collections::HashMap;
+use crate::error::CrustyError;
+
+/*
+    Book-keeping for a single #if / #ifdef / #ifndef block.
+*/
+struct Conditional {
+    /* Is the branch we're currently inside being emitted? */
+    active: bool,
+    /* Has any branch of this block been taken yet? (used by #elif/#else) */
+    taken: bool,
+    /* Was the enclosing block emitting when this block was opened? */
+    parent_active: bool,
+    /* Has the #else branch already been seen? */
+    seen_else: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CondToken {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+/*
+    A #define'd macro, function-like macros also have a parameter list.
+*/
+struct Macro {
+    params: Option<Vec<String>>,
+    body: String,
+}
+
+pub struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    conditionals: Vec<Conditional>,
+    file: String,
+    line: usize,    /* logical line number, can be overridden with #line */
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self {
+            macros: HashMap::new(),
+            conditionals: Vec::new(),
+            file: String::new(),
+            line: 0,
+        }
+    }
+
+    /*
+        Name of the file being processed, used in diagnostics.
+    */
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = file.to_string();
+        self
+    }
+
+    /*
+        Runs the preprocessor over the source and returns the text handed to the lexer.
+        Directive lines and lines inside untaken branches are replaced with blank
+        lines so that line numbers reported later still match the original file.
+        #line directives are the exception, they're passed on so the lexer can
+        renumber the tokens that follow.
+    */
+    pub fn process(&mut self, source: &str) -> Result<String, CrustyError> {
+        let mut output = String::new();
+
+        /* Normalize \r\n and lone \r line endings here so nothing after this sees them */
+        let source = source.replace("\r\n", "\n").replace('\r', "\n");
+
+        for line in source.lines() {
+            self.line += 1;
+
+            if let Some(directive) = line.trim_start().strip_prefix('#') {
+                if let Some(marker) = self.process_directive(directive)? {
+                    output.push_str(&marker);
+                }
+            } else if self.is_active() {
+                output.push_str(&self.expand(line, &mut Vec::new())?);
+            }
+
+            output.push('\n');
+        }
+
+        if !self.conditionals.is_empty() {
+            return Err(self.error("unterminated conditional directive, expected #endif"));
+        }
+
+        Ok(output)
+    }
+
+    /*
+        A diagnostic at the current logical position.
+    */
+    fn error(&self, msg: &str) -> CrustyError {
+        CrustyError::Preprocess { msg: msg.to_string(), file: self.file.clone(), line: self.line }
+    }
+
+    /*
+        Are we currently emitting lines? Only true when every enclosing conditional is taken.
+    */
+    fn is_active(&self) -> bool {
+        self.conditionals.last().is_none_or(|c| c.active)
+    }
+
+    /*
+        Handles a single directive line, the leading '#' has already been stripped.
+        Returns a line marker when the directive has to be forwarded to the lexer.
+    */
+    fn process_directive(&mut self, directive: &str) -> Result<Option<String>, CrustyError> {
+        let directive = strip_comment(directive).trim();
+        let (name, rest) = match directive.find(char::is_whitespace) {
+            Some(idx) => (&directive[..idx], directive[idx..].trim()),
+            None => (directive, ""),
+        };
+
+        match name {
+            "define" => {
+                if !self.is_active() {
+                    return Ok(None);
+                }
+
+                let macro_name = read_identifier(rest)
+                    .ok_or_else(|| self.error("expected a macro name after #define"))?;
+                let rest = &rest[macro_name.len()..];
+
+                /* Only a '(' directly after the name starts a parameter list, `#define X (1)` is an object macro */
+                let (params, body) = match rest.strip_prefix('(') {
+                    Some(list) => {
+                        let close = list.find(')')
+                            .ok_or_else(|| self.error("expected ')' to close the macro parameter list"))?;
+                        (Some(self.parse_macro_params(&list[..close])?), &list[close + 1..])
+                    }
+                    None => (None, rest),
+                };
+
+                self.macros.insert(macro_name.to_string(), Macro { params, body: body.trim().to_string() });
+            }
+
+            "undef" => {
+                if !self.is_active() {
+                    return Ok(None);
+                }
+
+                let macro_name = read_identifier(rest)
+                    .ok_or_else(|| self.error("expected a macro name after #undef"))?;
+                self.macros.remove(macro_name);
+            }
+
+            "ifdef" | "ifndef" => {
+                let macro_name = read_identifier(rest)
+                    .ok_or_else(|| self.error(&format!("expected a macro name after #{}", name)))?;
+                let defined = self.is_defined(macro_name);
+                let cond = if name == "ifdef" { defined } else { !defined };
+                self.open_conditional(cond);
+            }
+
+            "if" => {
+                /* Don't evaluate conditions inside untaken branches, they may not even be valid. */
+                let cond = self.is_active() && self.evaluate(rest)? != 0;
+                self.open_conditional(cond);
+            }
+
+            "elif" => {
+                let evaluate = match self.conditionals.last() {
+                    Some(c) if c.seen_else => return Err(self.error("#elif after #else")),
+                    Some(c) => c.parent_active && !c.taken,
+                    None => return Err(self.error("#elif without #if")),
+                };
+
+                let cond = evaluate && self.evaluate(rest)? != 0;
+                let current = self.conditionals.last_mut().unwrap();
+                current.active = cond;
+                current.taken |= cond;
+            }
+
+            "else" => {
+                match self.conditionals.last() {
+                    Some(c) if c.seen_else => return Err(self.error("duplicate #else")),
+                    Some(_) => {}
+                    None => return Err(self.error("#else without #if")),
+                }
+
+                let current = self.conditionals.last_mut().unwrap();
+                current.seen_else = true;
+                current.active = current.parent_active && !current.taken;
+                current.taken = true;
+            }
+
+            "endif" => {
+                self.conditionals.pop()
+                    .ok_or_else(|| self.error("#endif without #if"))?;
+            }
+
+            "line" => {
+                if !self.is_active() {
+                    return Ok(None);
+                }
+
+                return self.process_line_directive(rest).map(Some);
+            }
+
+            /* Untaken branches may hold directives for other compilers */
+            _ if !self.is_active() => {}
+            _ => return Err(self.error(&format!("unknown directive '#{}'", name))),
+        }
+
+        Ok(None)
+    }
+
+    /*
+        #line N "file"
+        The line after the directive becomes line N (of "file" when given).
+    */
+    fn process_line_directive(&mut self, rest: &str) -> Result<String, CrustyError> {
+        let (number, file) = match rest.find(char::is_whitespace) {
+            Some(idx) => (&rest[..idx], rest[idx..].trim()),
+            None => (rest, ""),
+        };
+
+        let number = number.parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| self.error(&format!("invalid line number '{}' in #line", number)))?;
+
+        if !file.is_empty() {
+            self.file = file.strip_prefix('"')
+                .and_then(|f| f.strip_suffix('"'))
+                .ok_or_else(|| self.error("expected a quoted file name in #line"))?
+                .to_string();
+        }
+
+        /* process() bumps the line before handling the next one */
+        self.line = number - 1;
+        Ok(format!("#line {} \"{}\"", number, self.file))
+    }
+
+    /*
+        Parses the comma separated parameter names of a function-like macro.
+    */
+    fn parse_macro_params(&self, list: &str) -> Result<Vec<String>, CrustyError> {
+        if list.trim().is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut params: Vec<String> = vec![];
+        for param in list.split(',').map(str::trim) {
+            if read_identifier(param) != Some(param) {
+                return Err(self.error(&format!("invalid macro parameter '{}'", param)));
+            }
+
+            if params.iter().any(|p| p == param) {
+                return Err(self.error(&format!("duplicate macro parameter '{}'", param)));
+            }
+
+            params.push(param.to_string());
+        }
+
+        Ok(params)
+    }
+
+    fn open_conditional(&mut self, cond: bool) {
+        let parent_active = self.is_active();
+
+        self.conditionals.push(Conditional {
+            active: parent_active && cond,
+            taken: cond,
+            parent_active,
+            seen_else: false,
+        });
+    }
+
+    /*
+        Replaces every macro name in a line with its body.
+        String literals and comments are copied through untouched, and a macro is never
+        expanded inside its own expansion so self-referencing macros can't loop forever.
+        Function-like macros are only expanded when followed by an argument list, the
+        arguments are expanded before being substituted for the parameters.
+    */
+    fn expand(&self, line: &str, expanding: &mut Vec<String>) -> Result<String, CrustyError> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut output = String::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let ch = chars[i];
+
+            match ch {
+                /* Copy string and char literals through verbatim */
+                '"' | '\'' => {
+                    output.push(ch);
+                    i += 1;
+
+                    while i < chars.len() {
+                        output.push(chars[i]);
+
+                        if chars[i] == '\\' && i + 1 < chars.len() {
+                            output.push(chars[i + 1]);
+                            i += 1;
+                        } else if chars[i] == ch {
+                            i += 1;
+                            break;
+                        }
+
+                        i += 1;
+                    }
+                }
+
+                /* The rest of the line is a comment */
+                '-' if chars.get(i + 1) == Some(&'-') => {
+                    output.extend(&chars[i..]);
+                    break;
+                }
+
+                /* Numbers may contain letters (suffixes, hex digits), never expand inside them */
+                c if c.is_ascii_digit() => {
+                    while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                        output.push(chars[i]);
+                        i += 1;
+                    }
+                }
+
+                c if c.is_alphabetic() || c == '_' => {
+                    let start = i;
+                    while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                        i += 1;
+                    }
+
+                    let ident: String = chars[start..i].iter().collect();
+                    if let Some(value) = self.builtin_macro(&ident) {
+                        output.push_str(&value);
+                        continue;
+                    }
+
+                    let mac = match self.macros.get(&ident) {
+                        Some(mac) if !expanding.contains(&ident) => mac,
+                        _ => {
+                            output.push_str(&ident);
+                            continue;
+                        }
+                    };
+
+                    let body = match &mac.params {
+                        None => mac.body.clone(),
+                        Some(params) => {
+                            let open = (i..chars.len()).find(|&j| !chars[j].is_whitespace());
+                            let open = match open {
+                                Some(open) if chars[open] == '(' => open,
+                                /* A function-like macro name that isn't called is left alone */
+                                _ => {
+                                    output.push_str(&ident);
+                                    continue;
+                                }
+                            };
+
+                            let (args, end) = self.collect_macro_args(&ident, &chars, open)?;
+                            i = end;
+
+                            let args = match args.as_slice() {
+                                [arg] if params.is_empty() && arg.is_empty() => vec![],
+                                _ => args,
+                            };
+
+                            if args.len() != params.len() {
+                                return Err(self.error(&format!(
+                                    "macro '{}' expects {} argument(s) but got {}",
+                                    ident, params.len(), args.len()
+                                )));
+                            }
+
+                            let args = args.iter()
+                                .map(|arg| self.expand(arg, expanding))
+                                .collect::<Result<Vec<_>, _>>()?;
+
+                            substitute_params(&mac.body, params, &args)
+                        }
+                    };
+
+                    expanding.push(ident);
+                    output.push_str(&self.expand(&body, expanding)?);
+                    expanding.pop();
+                }
+
+                _ => {
+                    output.push(ch);
+                    i += 1;
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    /*
+        Predefined macros, they track the current logical position so #line affects them.
+    */
+    fn builtin_macro(&self, name: &str) -> Option<String> {
+        match name {
+            "__LINE__" => Some(self.line.to_string()),
+            "__FILE__" => Some(format!("\"{}\"", self.file.replace('\\', "\\\\").replace('"', "\\\""))),
+            _ => None,
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.macros.contains_key(name) || self.builtin_macro(name).is_some()
+    }
+
+    /*
+        Splits the argument list of a function-like macro call starting at `open` (the '(')
+        on top level commas. Returns the trimmed arguments and the index after the ')'.
+    */
+    fn collect_macro_args(&self, name: &str, chars: &[char], open: usize) -> Result<(Vec<String>, usize), CrustyError> {
+        let mut args = vec![];
+        let mut current = String::new();
+        let mut depth = 0;
+        let mut quote = None;
+        let mut i = open + 1;
+
+        while i < chars.len() {
+            let ch = chars[i];
+            i += 1;
+
+            match quote {
+                Some(_) if ch == '\\' && i < chars.len() => {
+                    current.push(ch);
+                    current.push(chars[i]);
+                    i += 1;
+                    continue;
+                }
+                Some(q) if ch == q => quote = None,
+                Some(_) => {}
+                None if ch == '"' || ch == '\'' => quote = Some(ch),
+                None if ch == '(' => depth += 1,
+                None if ch == ')' && depth == 0 => {
+                    args.push(current.trim().to_string());
+                    return Ok((args, i));
+                }
+                None if ch == ')' => depth -= 1,
+                None if ch == ',' && depth == 0 => {
+                    args.push(current.trim().to_string());
+                    current.clear();
+                    continue;
+                }
+                None => {}
+            }
+
+            current.push(ch);
+        }
+
+        Err(self.error(&format!("unterminated argument list for macro '{}'", name)))
+    }
+
+    /*
+        Evaluates the constant integer expression of an #if or #elif.
+        `defined NAME` and `defined(NAME)` are resolved first, then macros are expanded,
+        and any identifier left over evaluates to 0 just like in C.
+    */
+    fn evaluate(&self, expr: &str) -> Result<i64, CrustyError> {
+        let raw = tokenize_condition(expr).map_err(|e| self.error(&e))?;
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < raw.len() {
+            match &raw[i] {
+                CondToken::Ident(name) if name == "defined" => {
+                    let (target, consumed) = match (raw.get(i + 1), raw.get(i + 2), raw.get(i + 3)) {
+                        (Some(CondToken::LParen), Some(CondToken::Ident(n)), Some(CondToken::RParen)) => (n, 4),
+                        (Some(CondToken::Ident(n)), _, _) => (n, 2),
+                        _ => return Err(self.error("expected a macro name after 'defined'")),
+                    };
+
+                    tokens.push(CondToken::Number(self.is_defined(target) as i64));
+                    i += consumed;
+                }
+
+                CondToken::Ident(name) if self.is_defined(name) => {
+                    let expanded = self.expand(name, &mut Vec::new())?;
+                    for token in tokenize_condition(&expanded).map_err(|e| self.error(&e))? {
+                        match token {
+                            CondToken::Ident(_) => tokens.push(CondToken::Number(0)),
+                            other => tokens.push(other),
+                        }
+                    }
+                    i += 1;
+                }
+
+                CondToken::Ident(_) => {
+                    tokens.push(CondToken::Number(0));
+                    i += 1;
+                }
+
+                other => {
+                    tokens.push(other.clone());
+                    i += 1;
+                }
+            }
+        }
+
+        let mut evaluator = ConditionEvaluator { tokens, pos: 0 };
+        let value = evaluator.parse_precedence(0).map_err(|e| self.error(&e))?;
+
+        if evaluator.pos != evaluator.tokens.len() {
+            return Err(self.error("unexpected trailing tokens in #if expression"));
+        }
+
+        Ok(value)
+    }
+}
+
+/*
+    A small precedence climbing evaluator for #if expressions,
+    mirrors the way the parser handles Binop precedence.
+*/
+struct ConditionEvaluator {
+    tokens: Vec<CondToken>,
+    pos: usize,
+}
+
+impl ConditionEvaluator {
+    fn precedence(op: &str) -> Option<u8> {
+        match op {
+            "||" => Some(1),
+            "&&" => Some(2),
+            "==" | "!=" => Some(3),
+            "<" | ">" | "<=" | ">=" => Some(4),
+            "+" | "-" => Some(5),
+            "*" | "/" | "%" => Some(6),
+            _ => None,
+        }
+    }
+
+    fn parse_precedence(&mut self, min: u8) -> Result<i64, String> {
+        let mut left = self.parse_unary()?;
+
+        while let Some(CondToken::Op(op)) = self.tokens.get(self.pos).cloned() {
+            let precedence = match Self::precedence(op) {
+                Some(p) if p >= min => p,
+                _ => break,
+            };
+
+            self.pos += 1;
+            let right = self.parse_precedence(precedence + 1)?;
+            left = self.apply(op, left, right)?;
+        }
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+
+        match token {
+            Some(CondToken::Number(n)) => Ok(n),
+            Some(CondToken::Op("!")) => Ok((self.parse_unary()? == 0) as i64),
+            Some(CondToken::Op("-")) => Ok(self.parse_unary()?.wrapping_neg()),
+            Some(CondToken::Op("+")) => self.parse_unary(),
+            Some(CondToken::LParen) => {
+                let value = self.parse_precedence(0)?;
+                match self.tokens.get(self.pos) {
+                    Some(CondToken::RParen) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("expected ')' in #if expression".to_string()),
+                }
+            }
+            Some(t) => Err(format!("unexpected {:?} in #if expression", t)),
+            None => Err("unexpected end of #if expression".to_string()),
+        }
+    }
+
+    fn apply(&self, op: &str, left: i64, right: i64) -> Result<i64, String> {
+        let value = match op {
+            "||" => (left != 0 || right != 0) as i64,
+            "&&" => (left != 0 && right != 0) as i64,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            "<" => (left < right) as i64,
+            ">" => (left > right) as i64,
+            "<=" => (left <= right) as i64,
+            ">=" => (left >= right) as i64,
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => {
+                return Err("division by zero in #if expression".to_string())
+            }
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            _ => unreachable!(),
+        };
+
+        Ok(value)
+    }
+}
+
+/*
+    Splits an #if expression into tokens.
+*/
+fn tokenize_condition(expr: &str) -> Result<Vec<CondToken>, String> {
+    const OPS: [&str; 14] = ["||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!"];
+
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+
+    while let Some(ch) = rest.chars().next() {
+        if ch.is_ascii_digit() {
+            let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            let value = rest[..len].parse::<i64>()
+                .map_err(|e| format!("{}", e))?;
+            tokens.push(CondToken::Number(value));
+            rest = &rest[len..];
+        } else if let Some(ident) = read_identifier(rest) {
+            tokens.push(CondToken::Ident(ident.to_string()));
+            rest = &rest[ident.len()..];
+        } else if ch == '(' || ch == ')' {
+            tokens.push(if ch == '(' { CondToken::LParen } else { CondToken::RParen });
+            rest = &rest[1..];
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(CondToken::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!("unexpected character '{}' in #if expression", ch));
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/*
+    Replaces each parameter name in a function-like macro body with its argument,
+    string and char literals in the body are left untouched.
+*/
+fn substitute_params(body: &str, params: &[String], args: &[String]) -> String {
+    let mut output = String::new();
+    let mut rest = body;
+
+    while let Some(ch) = rest.chars().next() {
+        if ch == '"' || ch == '\'' {
+            let mut escaped = false;
+            let len = rest[1..]
+                .char_indices()
+                .find(|&(_, c)| {
+                    let end = c == ch && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    end
+                })
+                .map_or(rest.len(), |(idx, _)| idx + 2);
+
+            output.push_str(&rest[..len]);
+            rest = &rest[len..];
+        } else if let Some(ident) = read_identifier(rest) {
+            match params.iter().position(|p| p == ident) {
+                Some(idx) => output.push_str(&args[idx]),
+                None => output.push_str(ident),
+            }
+
+            rest = &rest[ident.len()..];
+        } else {
+            /* Numbers are copied whole so a suffix or hex digit is never taken for a parameter */
+            let len = rest.find(|c: char| !c.is_alphanumeric() && c != '_').filter(|&n| n > 0).unwrap_or(ch.len_utf8());
+            output.push_str(&rest[..len]);
+            rest = &rest[len..];
+        }
+    }
+
+    output
+}
+
+/*
+    Returns the identifier at the start of the text, if there is one.
+*/
+fn read_identifier(text: &str) -> Option<&str> {
+    let first = text.chars().next()?;
+    if !(first.is_alphabetic() || first == '_') {
+        return None;
+    }
+
+    let len = text.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(text.len());
+    Some(&text[..len])
+}
+
+/*
+    Strips a trailing `--` comment from a directive.
+*/
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut prev = None;
+
+    for (idx, ch) in text.char_indices() {
+        match quote {
+            Some(q) if ch == q && prev != Some('\\') => quote = None,
+            Some(_) => {}
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == '-' && prev == Some('-') => return &text[..idx - 1],
+            None => {}
+        }
+
+        prev = Some(ch);
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(source: &str) -> Vec<String> {
+        Preprocessor::new().with_file("main.c").process(source).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn function_like_macro_substitutes_its_arguments() {
+        let lines = process("#define ADD(a, b) ((a) + (b))\nint x = ADD(1, 2);\nint y = ADD(f(1, 2), n * 3);\nchar* s = \"ADD(a, b)\";\n");
+
+        assert_eq!(lines, ["", "int x = ((1) + (2));", "int y = ((f(1, 2)) + (n * 3));", "char* s = \"ADD(a, b)\";"]);
+    }
+
+    #[test]
+    fn function_like_macro_doesnt_expand_itself() {
+        let lines = process("#define SELF(x) SELF(x + 1)\nint z = SELF(3);\n");
+
+        assert_eq!(lines[1], "int z = SELF(3 + 1);");
+    }
+
+    #[test]
+    fn line_and_file_expand_to_the_current_position() {
+        let lines = process("\n\n\n\nint line = __LINE__;\nchar* file = __FILE__;\n#line 100 \"gen.cr\"\nint moved = __LINE__; char* renamed = __FILE__;\n");
+
+        assert_eq!(lines[4], "int line = 5;");
+        assert_eq!(lines[5], "char* file = \"main.c\";");
+        assert_eq!(lines[7], "int moved = 100; char* renamed = \"gen.cr\";");
+    }
+}
@@ -0,0 +1,153 @@
+use crate::ast::{Expr, FunctionDecl, Stmt, VariableDecl};
+
+/*
+    Renders the parse tree as an indented outline, one node per line with its children
+    below it, e.g.
+        Return (line 3)
+          BinaryOp Add
+            Int 1
+            Identifier x
+*/
+pub fn print_ast(stmts: &[Stmt]) -> String {
+    let mut printer = Printer { output: String::new(), depth: 0 };
+    printer.stmts(stmts);
+    printer.output
+}
+
+struct Printer {
+    output: String,
+    depth: usize,
+}
+
+impl Printer {
+    fn line(&mut self, text: &str) {
+        self.output.push_str(&"  ".repeat(self.depth));
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    /*
+        Prints a node's header and then its children one level deeper.
+    */
+    fn node(&mut self, text: &str, children: impl FnOnce(&mut Self)) {
+        self.line(text);
+        self.depth += 1;
+        children(self);
+        self.depth -= 1;
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let line = stmt.line();
+
+        match stmt {
+            Stmt::FunctionDecl(fdecl) => self.function(fdecl),
+            Stmt::VariableDecl(vdecl) => self.variable(vdecl),
+            Stmt::MultiDecl(vdecls) => self.node(&format!("MultiDecl (line {})", line), |p| {
+                for vdecl in vdecls {
+                    p.variable(vdecl);
+                }
+            }),
+            Stmt::ArrayDecl(adecl) => {
+                self.line(&format!("ArrayDecl {} {}[{}] (line {})", adecl.data_type, adecl.name, adecl.size, line));
+            }
+            Stmt::ExternDecl(decl) => match &decl.params {
+                Some(params) => {
+                    let param_types: Vec<&str> = params.iter().map(|param| param.data_type.as_str()).collect();
+                    self.line(&format!("ExternDecl {} {}({}) (line {})", decl.data_type, decl.name, param_types.join(", "), line));
+                }
+                None => self.line(&format!("ExternDecl {} {} (line {})", decl.data_type, decl.name, line)),
+            },
+            Stmt::Assign(assign) => {
+                let target = if assign.deref { format!("*{}", assign.name) } else { assign.name.clone() };
+                self.node(&format!("Assign {} (line {})", target, line), |p| {
+                    if let Some(index) = &assign.index {
+                        p.node("index", |p| p.expr(index));
+                    }
+                    p.expr(&assign.value);
+                });
+            }
+            Stmt::Return(ret) => self.node(&format!("Return (line {})", line), |p| p.expr(&ret.value)),
+            Stmt::Expression(expr, _) => self.node(&format!("Expression (line {})", line), |p| p.expr(expr)),
+            Stmt::If(if_stmt) => {
+                self.node(&format!("If (line {})", line), |p| {
+                    p.node("cond", |p| p.expr(&if_stmt.cond));
+                    p.node("then", |p| p.stmts(&if_stmt.then_body));
+                    if let Some(else_body) = &if_stmt.else_body {
+                        p.node("else", |p| p.stmts(else_body));
+                    }
+                });
+            }
+            Stmt::While(while_stmt) => {
+                self.node(&format!("While (line {})", line), |p| {
+                    p.node("cond", |p| p.expr(&while_stmt.cond));
+                    p.node("body", |p| p.stmts(&while_stmt.body));
+                });
+            }
+        }
+    }
+
+    fn variable(&mut self, vdecl: &VariableDecl) {
+        let register = if vdecl.register { "register " } else { "" };
+        self.node(&format!("VariableDecl {}{} {} (line {})", register, vdecl.data_type, vdecl.name, vdecl.line), |p| p.expr(&vdecl.value));
+    }
+
+    fn function(&mut self, fdecl: &FunctionDecl) {
+        let mut header = format!("FunctionDecl {} {}", fdecl.data_type, fdecl.name);
+        if fdecl.is_static {
+            header = format!("static {}", header);
+        }
+        for attribute in &fdecl.attributes {
+            header.push_str(&format!(" @{:?}", attribute));
+        }
+        header.push_str(&format!(" (line {})", fdecl.line));
+
+        self.node(&header, |p| {
+            if !fdecl.params.is_empty() {
+                p.node("params", |p| {
+                    for param in &fdecl.params {
+                        let restrict = if param.restrict { " restrict" } else { "" };
+                        p.line(&format!("{}{} {}", param.data_type, restrict, param.name));
+                    }
+                });
+            }
+            p.node("body", |p| p.stmts(&fdecl.body));
+        });
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Identifier { name, .. } => self.line(&format!("Identifier {}", name)),
+            Expr::Int(n) => self.line(&format!("Int {}", n)),
+            Expr::Float(value) => self.line(&format!("Float {:?}", value)),
+            Expr::String(s) => self.line(&format!("String {:?}", s)),
+            Expr::Char(c) => self.line(&format!("Char {:?}", *c as char)),
+            Expr::BinaryOp { left, op, right } => self.node(&format!("BinaryOp {:?}", op), |p| {
+                p.expr(left);
+                p.expr(right);
+            }),
+            Expr::FunctionCall { callee, args } => self.node(&format!("Call {}", callee), |p| {
+                for arg in args {
+                    p.expr(arg);
+                }
+            }),
+            Expr::Unary { op, operand } => self.node(&format!("Unary {:?}", op), |p| p.expr(operand)),
+            Expr::Deref(pointer) => self.node("Deref", |p| p.expr(pointer)),
+            Expr::Comma(exprs) => self.node("Comma", |p| {
+                for expr in exprs {
+                    p.expr(expr);
+                }
+            }),
+            Expr::AddrOf(operand) => self.node("AddrOf", |p| p.expr(operand)),
+            Expr::Index { base, index } => self.node("Index", |p| {
+                p.expr(base);
+                p.expr(index);
+            }),
+        }
+    }
+}
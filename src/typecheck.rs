@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use crate::ast::{Binop, Expr, ExternDecl, FunctionDecl, Stmt, UnaryOp, VariableDecl};
+use crate::codegen::{is_library_function, library_return_type};
+use crate::error::CrustyError;
+
+/*
+    Types that convert into each other implicitly, like C's arithmetic types.
+*/
+const NUMERIC_TYPES: [&str; 5] = ["int", "char", "bool", "long", "double"];
+
+struct Signature {
+    return_type: String,
+    param_types: Vec<String>,
+}
+
+struct Variable {
+    data_type: String,
+    register: bool,     /* declared `register`, its address can't be taken */
+}
+
+/*
+    Checks that values are used according to their declared types before any code is
+    generated: initializers and assignments must be compatible with the variable, return
+    values with the function, and calls to functions defined in the file must pass the
+    right number of compatible arguments, as do calls through an extern prototype. Calls
+    to common C library functions aren't checked since there's no declaration to check
+    them against, a call to any other undeclared function is an error.
+*/
+pub fn typecheck(stmts: &[Stmt]) -> Result<(), CrustyError> {
+    let mut checker = TypeChecker {
+        scopes: vec![HashMap::new()],
+        functions: HashMap::new(),
+        return_type: String::new(),
+    };
+
+    /* signatures first, a call may come before the definition */
+    for stmt in stmts {
+        match stmt {
+            Stmt::FunctionDecl(fdecl) => {
+                checker.functions.insert(fdecl.name.clone(), Signature {
+                    return_type: fdecl.data_type.clone(),
+                    param_types: fdecl.params.iter().map(|param| param.data_type.clone()).collect(),
+                });
+            }
+            Stmt::ExternDecl(ExternDecl { data_type, name, params: Some(params), .. }) => {
+                checker.functions.entry(name.clone()).or_insert(Signature {
+                    return_type: data_type.clone(),
+                    param_types: params.iter().map(|param| param.data_type.clone()).collect(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    checker.check_block(stmts)
+}
+
+struct TypeChecker {
+    scopes: Vec<HashMap<String, Variable>>,    /* innermost scope last */
+    functions: HashMap<String, Signature>,
+    return_type: String,                    /* of the function being checked */
+}
+
+impl TypeChecker {
+    fn check_block(&mut self, stmts: &[Stmt]) -> Result<(), CrustyError> {
+        for stmt in stmts {
+            self.check_stmt(stmt)?;
+        }
+
+        Ok(())
+    }
+
+    /*
+        Checks a nested body, declarations inside it go out of scope at the end.
+    */
+    fn check_scoped(&mut self, stmts: &[Stmt]) -> Result<(), CrustyError> {
+        self.scopes.push(HashMap::new());
+        let result = self.check_block(stmts);
+        self.scopes.pop();
+        result
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) -> Result<(), CrustyError> {
+        let line = stmt.line();
+
+        match stmt {
+            Stmt::FunctionDecl(fdecl) => self.check_function(fdecl),
+            Stmt::VariableDecl(vdecl) => self.check_var_decl(vdecl),
+            Stmt::MultiDecl(vdecls) => {
+                for vdecl in vdecls {
+                    self.check_var_decl(vdecl)?;
+                }
+                Ok(())
+            }
+            Stmt::ArrayDecl(adecl) => {
+                self.declare(&adecl.name, &format!("{}[]", adecl.data_type), false);
+                Ok(())
+            }
+            Stmt::ExternDecl(decl) => {
+                if decl.params.is_none() {
+                    self.declare(&decl.name, &decl.data_type, false);
+                }
+                Ok(())
+            }
+            Stmt::Assign(assign) => {
+                let mut var_type = self.lookup(&assign.name)
+                    .ok_or_else(|| type_error(line, format!("assignment to undefined variable '{}'", assign.name)))?;
+                if let Some(index) = &assign.index {
+                    var_type = self.element_type(&var_type, &assign.name, index, line)?;
+                } else if assign.deref {
+                    var_type = var_type.strip_suffix('*')
+                        .ok_or_else(|| type_error(line, format!("cannot dereference '{}' variable '{}'", var_type, assign.name)))?
+                        .to_string();
+                }
+
+                let value_type = self.expr_type(&assign.value, line)?;
+                if !compatible(&var_type, &value_type) {
+                    return Err(type_error(line, format!("cannot assign '{}' to '{}' variable '{}'", value_type, var_type, assign.name)));
+                }
+
+                Ok(())
+            }
+            Stmt::Return(ret) => {
+                let value_type = self.expr_type(&ret.value, line)?;
+                if !compatible(&self.return_type, &value_type) {
+                    return Err(type_error(line, format!("cannot return '{}' from a function returning '{}'", value_type, self.return_type)));
+                }
+
+                Ok(())
+            }
+            Stmt::If(if_stmt) => {
+                self.expr_type(&if_stmt.cond, line)?;
+                self.check_scoped(&if_stmt.then_body)?;
+                match &if_stmt.else_body {
+                    Some(else_body) => self.check_scoped(else_body),
+                    None => Ok(()),
+                }
+            }
+            Stmt::While(while_stmt) => {
+                self.expr_type(&while_stmt.cond, line)?;
+                self.check_scoped(&while_stmt.body)
+            }
+            Stmt::Expression(expr, _) => {
+                self.expr_type(expr, line)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn check_var_decl(&mut self, vdecl: &VariableDecl) -> Result<(), CrustyError> {
+        let value_type = self.expr_type(&vdecl.value, vdecl.line)?;
+        if !compatible(&vdecl.data_type, &value_type) {
+            return Err(type_error(vdecl.line, format!("cannot initialize '{}' variable '{}' with '{}'", vdecl.data_type, vdecl.name, value_type)));
+        }
+
+        self.declare(&vdecl.name, &vdecl.data_type, vdecl.register);
+        Ok(())
+    }
+
+    fn check_function(&mut self, fdecl: &FunctionDecl) -> Result<(), CrustyError> {
+        self.return_type = fdecl.data_type.clone();
+
+        self.scopes.push(HashMap::new());
+        for param in &fdecl.params {
+            self.declare(&param.name, &param.data_type, false);
+        }
+
+        let result = self.check_block(&fdecl.body);
+        self.scopes.pop();
+
+        /* statements only know their line, the file comes from the function they're in */
+        result.map_err(|e| match e {
+            CrustyError::Type { msg, line, col, .. } => CrustyError::Type { msg, file: fdecl.file.clone(), line, col },
+            e => e,
+        })
+    }
+
+    /*
+        Works out the type of an expression, checking its operands and calls on the way.
+    */
+    fn expr_type(&self, expr: &Expr, line: usize) -> Result<String, CrustyError> {
+        match expr {
+            Expr::Int(n) if i32::try_from(*n).is_err() => Ok("long".to_string()),
+            Expr::Int(_) => Ok("int".to_string()),
+            Expr::Float(_) => Ok("double".to_string()),
+            Expr::String(_) => Ok("char*".to_string()),
+            Expr::Char(_) => Ok("char".to_string()),
+            Expr::Identifier { name, line, column } => self.lookup(name)
+                .ok_or_else(|| CrustyError::Type {
+                    msg: format!("undefined variable '{}'", name),
+                    file: String::new(),
+                    line: *line,
+                    col: *column,
+                }),
+            Expr::FunctionCall { callee, args } => self.call_type(callee, args, line),
+            Expr::Comma(exprs) => {
+                let mut last_type = String::new();
+                for expr in exprs {
+                    last_type = self.expr_type(expr, line)?;
+                }
+
+                Ok(last_type)
+            }
+            Expr::Deref(pointer) => {
+                let pointer_type = self.expr_type(pointer, line)?;
+                match pointer_type.strip_suffix('*') {
+                    Some(pointee) => Ok(pointee.to_string()),
+                    None => Err(type_error(line, format!("cannot dereference '{}'", pointer_type))),
+                }
+            }
+            Expr::AddrOf(operand) => match operand.as_ref() {
+                Expr::Identifier { name, .. } if self.variable(name).is_some_and(|variable| variable.register) => {
+                    Err(type_error(line, format!("cannot take the address of register variable '{}'", name)))
+                }
+                Expr::Identifier { .. } | Expr::Index { .. } => Ok(format!("{}*", self.expr_type(operand, line)?)),
+                _ => Err(type_error(line, "can only take the address of a variable or an array element".to_string())),
+            },
+            Expr::Index { base, index } => {
+                let Expr::Identifier { name, .. } = base.as_ref() else {
+                    return Err(type_error(line, "only named arrays can be indexed".to_string()));
+                };
+
+                let array_type = self.expr_type(base, line)?;
+                self.element_type(&array_type, name, index, line)
+            }
+            Expr::Unary { op, operand } => {
+                let operand_type = self.expr_type(operand, line)?;
+                match op {
+                    UnaryOp::Neg if operand_type.ends_with('*') => Err(type_error(line, format!("cannot negate a '{}'", operand_type))),
+                    UnaryOp::Neg => Ok(promote(&operand_type, "int")),
+                    UnaryOp::Not => Ok("int".to_string()),
+                }
+            }
+            Expr::BinaryOp { left, op, right } => {
+                let left_type = self.expr_type(left, line)?;
+                let right_type = self.expr_type(right, line)?;
+
+                match op {
+                    Binop::And | Binop::Or => Ok("int".to_string()),
+                    /* pointers can only be compared with each other */
+                    Binop::Eq | Binop::Ne | Binop::Lt | Binop::Gt | Binop::Le | Binop::Ge => {
+                        if compatible(&left_type, &right_type) {
+                            Ok("int".to_string())
+                        } else {
+                            Err(type_error(line, format!("cannot compare '{}' with '{}'", left_type, right_type)))
+                        }
+                    }
+                    Binop::Add | Binop::Sub | Binop::Mul | Binop::Div | Binop::Mod => {
+                        if let Binop::Add = op
+                            && left_type == "char*" && right_type == "char*"
+                        {
+                            return Err(type_error(line, "only string literals can be concatenated, strings aren't joined at runtime".to_string()));
+                        }
+
+                        /* a pointer moves by whole elements, two pointers are apart by a count of them */
+                        match (op, left_type.ends_with('*'), right_type.ends_with('*')) {
+                            (Binop::Add | Binop::Sub, true, false) if is_integer(&right_type) => return Ok(left_type),
+                            (Binop::Add, false, true) if is_integer(&left_type) => return Ok(right_type),
+                            (Binop::Sub, true, true) if left_type == right_type => return Ok("long".to_string()),
+                            _ => {}
+                        }
+
+                        if left_type.ends_with('*') || right_type.ends_with('*') {
+                            return Err(type_error(line, format!("invalid operands '{}' and '{}' to {:?}", left_type, right_type, op)));
+                        }
+
+                        if let Binop::Mod = op
+                            && (left_type == "double" || right_type == "double")
+                        {
+                            return Err(type_error(line, format!("'%' needs integer operands, got '{}' and '{}'", left_type, right_type)));
+                        }
+
+                        Ok(promote(&left_type, &right_type))
+                    }
+                }
+            }
+        }
+    }
+
+    /*
+        Checks a call against the callee's signature, returning what it returns.
+        Functions without a definition or prototype in the file are external, their
+        arguments aren't checked and they return int unless the library table says
+        otherwise.
+    */
+    fn call_type(&self, callee: &str, args: &[Expr], line: usize) -> Result<String, CrustyError> {
+        let arg_types = args.iter()
+            .map(|arg| self.expr_type(arg, line))
+            .collect::<Result<Vec<String>, CrustyError>>()?;
+
+        let Some(signature) = self.functions.get(callee) else {
+            if !is_library_function(callee) {
+                return Err(type_error(line, format!("unknown function '{}'", callee)));
+            }
+            return Ok(library_return_type(callee).unwrap_or("int").to_string());
+        };
+
+        if arg_types.len() != signature.param_types.len() {
+            return Err(type_error(line, format!("function '{}' expects {} arguments but got {}", callee, signature.param_types.len(), arg_types.len())));
+        }
+
+        for (i, (param_type, arg_type)) in signature.param_types.iter().zip(&arg_types).enumerate() {
+            if !compatible(param_type, arg_type) {
+                return Err(type_error(line, format!("argument {} of '{}' expects '{}' but got '{}'", i + 1, callee, param_type, arg_type)));
+            }
+        }
+
+        Ok(signature.return_type.clone())
+    }
+
+    /*
+        Type of an element of the array `name`, the index has to be an integer.
+    */
+    fn element_type(&self, array_type: &str, name: &str, index: &Expr, line: usize) -> Result<String, CrustyError> {
+        let Some(elem_type) = array_type.strip_suffix("[]") else {
+            return Err(type_error(line, format!("'{}' is not an array", name)));
+        };
+
+        let index_type = self.expr_type(index, line)?;
+        if index_type == "double" || !compatible("int", &index_type) {
+            return Err(type_error(line, format!("array index must be an integer, got '{}'", index_type)));
+        }
+
+        Ok(elem_type.to_string())
+    }
+
+    fn declare(&mut self, name: &str, data_type: &str, register: bool) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), Variable { data_type: data_type.to_string(), register });
+    }
+
+    fn variable(&self, name: &str) -> Option<&Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        self.variable(name).map(|variable| variable.data_type.clone())
+    }
+}
+
+/*
+    Whether a value of one type can be stored in another. The implicit `auto` type
+    accepts anything.
+*/
+fn compatible(target: &str, value: &str) -> bool {
+    if target == "auto" || value == "auto" || target == value {
+        return true;
+    }
+
+    NUMERIC_TYPES.contains(&target) && NUMERIC_TYPES.contains(&value)
+}
+
+/*
+    Whether a value of the type can offset a pointer.
+*/
+fn is_integer(data_type: &str) -> bool {
+    data_type != "double" && NUMERIC_TYPES.contains(&data_type)
+}
+
+/*
+    Result type of arithmetic on two numeric operands, the widest of the two.
+*/
+fn promote(left: &str, right: &str) -> String {
+    ["double", "long", "auto"].iter()
+        .find(|wide| left == **wide || right == **wide)
+        .map_or("int", |wide| wide)
+        .to_string()
+}
+
+fn type_error(line: usize, msg: String) -> CrustyError {
+    CrustyError::Type { msg, file: String::new(), line, col: 0 }
+}
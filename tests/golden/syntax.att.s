@@ -0,0 +1,52 @@
+.section .rodata
+.LC0:
+    .string "x"
+.section .text
+    .globl scale
+    .type scale, @function
+scale:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $32, %rsp
+    movq %rdi, -8(%rbp)
+    movl %esi, -12(%rbp)
+    leaq .LC0(%rip), %rax
+    movq %rax, -24(%rbp)
+    movq -8(%rbp), %rax
+    pushq %rax
+    movl -12(%rbp), %eax
+    cltq
+    movq %rax, %rcx
+    popq %rax
+    imulq %rcx, %rax
+    pushq %rax
+    movl $1, %eax
+    cltq
+    movq %rax, %rcx
+    popq %rax
+    addq %rcx, %rax
+    jmp .Lreturn_scale
+.Lreturn_scale:
+    leave
+    ret
+    .globl main
+    .type main, @function
+main:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $16, %rsp
+    movl $3, %eax
+    cltq
+    pushq %rax
+    popq %rdx
+    movl %edx, -4(%rbp)
+    movl -4(%rbp), %eax
+    pushq %rax
+    movq $2, %rdi
+    popq %rsi
+    call scale
+    jmp .Lreturn_main
+.Lreturn_main:
+    leave
+    ret
+.section .note.GNU-stack,"",@progbits
@@ -0,0 +1,10 @@
+long scale(long x, int n) {
+    char* s = "x";
+    return x * n + 1;
+}
+
+int main() {
+    int arr[2];
+    arr[1] = 3;
+    return scale(2, arr[1]);
+}
@@ -0,0 +1,53 @@
+.intel_syntax noprefix
+.section .rodata
+.LC0:
+    .string "x"
+.section .text
+    .globl scale
+    .type scale, @function
+scale:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32
+    mov QWORD PTR [rbp-8], rdi
+    mov DWORD PTR [rbp-12], esi
+    lea rax, [rip+.LC0]
+    mov QWORD PTR [rbp-24], rax
+    mov rax, QWORD PTR [rbp-8]
+    push rax
+    mov eax, DWORD PTR [rbp-12]
+    cdqe
+    mov rcx, rax
+    pop rax
+    imul rax, rcx
+    push rax
+    mov eax, 1
+    cdqe
+    mov rcx, rax
+    pop rax
+    add rax, rcx
+    jmp .Lreturn_scale
+.Lreturn_scale:
+    leave
+    ret
+    .globl main
+    .type main, @function
+main:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 16
+    mov eax, 3
+    cdqe
+    push rax
+    pop rdx
+    mov DWORD PTR [rbp-4], edx
+    mov eax, DWORD PTR [rbp-4]
+    push rax
+    mov rdi, 2
+    pop rsi
+    call scale
+    jmp .Lreturn_main
+.Lreturn_main:
+    leave
+    ret
+.section .note.GNU-stack,"",@progbits
@@ -0,0 +1,1008 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+/*
+    Compiles `source` with crusty into its own directory under the system temp dir and
+    runs it, returning the exit code and stdout. Every test passes a unique `name` so
+    they can run in parallel.
+*/
+fn run(name: &str, source: &str) -> (i32, String) {
+    run_with_args(name, &[], source)
+}
+
+/*
+    Like `run`, with extra command line arguments for crusty such as libraries to link.
+*/
+fn run_with_args(name: &str, args: &[&str], source: &str) -> (i32, String) {
+    let dir = work_dir(name);
+    let input = dir.join("main.c");
+    let exe = dir.join("main");
+    std::fs::write(&input, source).unwrap();
+
+    let compile = Command::new(env!("CARGO_BIN_EXE_crusty"))
+        .args(args)
+        .arg("-o").arg(&exe).arg(&input)
+        .output()
+        .unwrap();
+    assert!(compile.status.success(), "crusty failed:\n{}", String::from_utf8_lossy(&compile.stdout));
+
+    let output = Command::new(&exe).output().unwrap();
+    (output.status.code().unwrap(), String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/*
+    Runs `crusty --check` on `source`, which has to be rejected, and returns the
+    diagnostic it printed.
+*/
+fn check_error(name: &str, source: &str) -> String {
+    check_error_with_args(name, &[], source)
+}
+
+fn check_error_with_args(name: &str, args: &[&str], source: &str) -> String {
+    let dir = work_dir(name);
+    let input = dir.join("main.c");
+    std::fs::write(&input, source).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crusty"))
+        .arg("--check").arg("--color=never").args(args).arg(&input)
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "crusty accepted a program it should reject");
+    String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr)
+}
+
+/*
+    Compiles `source`, which has to fail in a stage --check doesn't run, such as codegen,
+    and returns the diagnostic crusty printed.
+*/
+fn compile_error(name: &str, source: &str) -> String {
+    compile_error_with_args(name, &[], source)
+}
+
+fn compile_error_with_args(name: &str, args: &[&str], source: &str) -> String {
+    let dir = work_dir(name);
+    let input = dir.join("main.c");
+    std::fs::write(&input, source).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crusty"))
+        .arg("--color=never").args(args)
+        .arg("-o").arg(dir.join("main")).arg(&input)
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "crusty accepted a program it should reject");
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/*
+    Compiles `source` with -S and returns the generated assembly.
+*/
+fn assembly(name: &str, args: &[&str], source: &str) -> String {
+    assembly_and_output(name, args, source).0
+}
+
+/*
+    Like `assembly`, also returning what crusty printed, such as warnings.
+*/
+fn assembly_and_output(name: &str, args: &[&str], source: &str) -> (String, String) {
+    let dir = work_dir(name);
+    let input = dir.join("main.c");
+    let output = dir.join("main.s");
+    std::fs::write(&input, source).unwrap();
+
+    let compile = Command::new(env!("CARGO_BIN_EXE_crusty"))
+        .arg("-S").arg("--color=never").args(args)
+        .arg("-o").arg(&output).arg(&input)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&compile.stdout).into_owned();
+    assert!(compile.status.success(), "crusty failed:\n{}", stdout);
+
+    (std::fs::read_to_string(output).unwrap(), stdout)
+}
+
+fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crusty-test-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn comma_statement_runs_every_call_in_order() {
+    let (code, stdout) = run("comma_statement", r#"
+        int f() { printf("f\n"); return 1; }
+        int g() { printf("g\n"); return 2; }
+        int main() {
+            (f(), g());
+            return 0;
+        }
+    "#);
+
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "f\ng\n");
+}
+
+#[test]
+fn expression_statement_is_evaluated() {
+    let (_, stdout) = run("expression_statement", r#"
+        int main() {
+            -printf("hi\n");
+            return 0;
+        }
+    "#);
+
+    assert_eq!(stdout, "hi\n");
+}
+
+#[test]
+fn extern_prototype_calls_a_library_function() {
+    let (code, stdout) = run_with_args("extern_prototype", &["-lm"], r#"
+        extern double sin(double x);
+        extern double pow(double, double);
+        int main() {
+            double zero = sin(0.0);
+            printf("%f %f\n", zero, pow(2.0, 10.0));
+            return 0;
+        }
+    "#);
+
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "0.000000 1024.000000\n");
+}
+
+#[test]
+fn undeclared_function_is_an_external_call() {
+    let (code, stdout) = run("undeclared_function", r#"
+        int main() {
+            puts("hi");
+            return abs(-3);
+        }
+    "#);
+
+    assert_eq!(code, 3);
+    assert_eq!(stdout, "hi\n");
+}
+
+#[test]
+fn unknown_function_is_rejected() {
+    let error = check_error("unknown_function", "int main() { return bar(1); }\n");
+
+    assert!(error.contains("unknown function 'bar'"), "{}", error);
+}
+
+#[test]
+fn mutually_recursive_functions_call_each_other() {
+    let (code, _) = run("mutual_recursion", r#"
+        int is_even(int n) {
+            if (n == 0) { return 1; }
+            return is_odd(n - 1);
+        }
+        int is_odd(int n) {
+            if (n == 0) { return 0; }
+            return is_even(n - 1);
+        }
+        int main() {
+            return is_even(10) + is_odd(7) * 2;
+        }
+    "#);
+
+    assert_eq!(code, 3);
+}
+
+#[test]
+fn library_calls_use_their_return_types() {
+    let (code, stdout) = run_with_args("library_return_types", &["-lm"], r#"
+        int main() {
+            char* s = malloc(16);
+            strcpy(s, "crusty");
+            double d = sqrt(16.0);
+            long n = strlen(s);
+            printf("%s %f %ld\n", s, d, n);
+            free(s);
+            return 0;
+        }
+    "#);
+
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "crusty 4.000000 6\n");
+}
+
+#[test]
+fn division_by_a_wide_constant_is_not_division_by_zero() {
+    let (code, _) = run("wide_divisor", r#"
+        int main() {
+            long y = 8589934592;
+            long x = y / 4294967296;
+            return x;
+        }
+    "#);
+
+    assert_eq!(code, 2);
+}
+
+#[test]
+fn binary_op_initializer_is_stored() {
+    let (code, _) = run("binary_op_initializer", "int main() { int y = 3 * 7; return y + 1; }");
+
+    assert_eq!(code, 22);
+}
+
+#[test]
+fn variable_initializers_are_copied() {
+    let (code, stdout) = run("variable_initializer", r#"
+        int main() {
+            int x = 5;
+            int y = x;
+            char* s = "copied";
+            char* t = s;
+            printf("%s\n", t);
+            return y;
+        }
+    "#);
+
+    assert_eq!(code, 5);
+    assert_eq!(stdout, "copied\n");
+}
+
+#[test]
+fn address_of_register_variable_is_rejected() {
+    let error = check_error("register_address", r#"
+        int main() {
+            register int x = 5;
+            int* p = &x;
+            *p = 6;
+            return x;
+        }
+    "#);
+
+    assert!(error.contains("cannot take the address of register variable 'x'"), "{}", error);
+}
+
+#[test]
+fn line_marker_renames_the_file_in_diagnostics() {
+    let parse_error = check_error("line_marker_parse", "#line 100 \"gen.cr\"\nint main() { return ; }\n");
+    assert!(parse_error.contains("line 100 of gen.cr"), "{}", parse_error);
+
+    let type_error = check_error("line_marker_type", "#line 100 \"gen.cr\"\nint main() {\n    char* s = 1.5;\n    return 0;\n}\n");
+    assert!(type_error.contains("line 101 of gen.cr: cannot initialize"), "{}", type_error);
+}
+
+#[test]
+fn if_directive_evaluates_constant_expressions() {
+    let (code, _) = run("if_directive", r#"
+        #if 1+1 == 2
+        int taken() { return 1; }
+        #else
+        int taken() { return 0; }
+        #endif
+
+        #if UNDEFINED
+        int skipped() { return 0; }
+        #else
+        int skipped() { return 2; }
+        #endif
+
+        #if 0
+        #pragma something crusty doesn't know
+        #endif
+
+        int main() { return taken() + skipped(); }
+    "#);
+
+    assert_eq!(code, 3);
+}
+
+#[test]
+fn double_conditions_compare_against_zero() {
+    let (code, stdout) = run("double_conditions", r#"
+        int main() {
+            double half = 0.5;
+            int steps = 0;
+            if (half) { printf("if\n"); }
+            if (half && 0.25) { printf("and\n"); }
+            if (0.0 || half) { printf("or\n"); }
+            while (half) {
+                half = half - 0.25;
+                steps = steps + 1;
+            }
+            return steps;
+        }
+    "#);
+
+    assert_eq!(code, 2);
+    assert_eq!(stdout, "if\nand\nor\n");
+}
+
+#[test]
+fn pedantic_rejects_implicit_auto() {
+    let source = r#"
+        static helper() { return 4; }
+        @cold other() { return 1; }
+        int main() { return helper() + other() * 0; }
+    "#;
+
+    let (code, _) = run("implicit_auto", source);
+    assert_eq!(code, 4);
+
+    let error = check_error_with_args("implicit_auto_pedantic", &["--pedantic"], source);
+    assert!(error.contains("the implicit 'auto' is rejected by --pedantic"), "{}", error);
+}
+
+#[test]
+fn pedantic_rejects_chained_comparisons() {
+    let (code, _) = run("chained_comparison", "int main() { return 3 > 2 > 1; }");
+    assert_eq!(code, 0);
+
+    let error = check_error_with_args("chained_comparison_pedantic", &["--pedantic"], "int main() { return 3 > 2 > 1; }");
+    assert!(error.contains("chained comparison"), "{}", error);
+
+    let (code, _) = run_with_args("parenthesized_comparison", &["--pedantic"], "int main() { return (3 > 2) == 1; }");
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn eight_argument_call_passes_stack_arguments() {
+    let (code, stdout) = run("eight_arguments", r#"
+        int sum(int a, int b, int c, int d, int e, int f, int g, int h) {
+            printf("%d %d %d %d %d %d %d %d\n", a, b, c, d, e, f, g, h);
+            return a + b + c + d + e + f + g + h;
+        }
+
+        int main() {
+            int direct = sum(1, 2, 3, 4, 5, 6, 7, 8);
+            int x = 4;
+            /* x is pushed while the call runs, the 7th and 8th arguments need padding */
+            int nested = x + sum(1, 2, 3, 4, 5, 6, 7, 8);
+            return direct + nested;
+        }
+    "#);
+
+    assert_eq!(code, 76);
+    assert_eq!(stdout, "1 2 3 4 5 6 7 8\n1 2 3 4 5 6 7 8\n");
+}
+
+#[test]
+fn nested_calls_in_binary_operands_keep_their_results() {
+    let (code, _) = run("nested_call_operands", r#"
+        int add(int a, int b) { return a + b; }
+        int main() { return add(1, 2) + add(3, 4) == 10; }
+    "#);
+
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn register_variable_compiles_and_runs() {
+    let (code, _) = run("register_variable", r#"
+        int main() {
+            register int x = 5;
+            register int y = x + 1, z = 2;
+            return x + y + z;
+        }
+    "#);
+
+    assert_eq!(code, 13);
+}
+
+#[test]
+fn register_without_a_data_type_is_rejected() {
+    let error = check_error("register_without_type", "int main() { register x = 5; return x; }");
+
+    assert!(error.contains("'register' needs a data type"), "{}", error);
+}
+
+#[test]
+fn errors_use_the_crusty_prefix() {
+    let preprocess_error = check_error("preprocess_error", "#bogus\nint main() { return 0; }\n");
+    assert!(preprocess_error.contains("[crusty::error] line 1 of "), "{}", preprocess_error);
+    assert!(preprocess_error.contains("unknown directive '#bogus'"), "{}", preprocess_error);
+
+    let parse_error = check_error("parse_error", "int main() {\n    return (1;\n}\n");
+    assert!(parse_error.contains("[crusty::error] line 2, col 14: expected RParen but got Semi"), "{}", parse_error);
+
+    let type_error = check_error("type_error", "int main() {\n    return bar();\n}\n");
+    assert!(type_error.starts_with("[crusty::error] line 2: unknown function 'bar'"), "{}", type_error);
+
+    for error in [preprocess_error, parse_error, type_error] {
+        assert_eq!(error.matches("[crusty").count(), 1, "{}", error);
+    }
+}
+
+#[test]
+fn codegen_errors_name_the_file_and_line() {
+    let error = compile_error("codegen_error", "#line 7 \"gen.cr\"\nint main() {\n    return 1 / 0;\n}\n");
+
+    assert!(error.starts_with("[crusty::error] line 8 of gen.cr: division by zero in 'main'"), "{}", error);
+}
+
+#[test]
+fn undefined_variable_reports_its_line_and_column() {
+    let error = check_error("undefined_variable", "int main() {\n    int a = 1;\n    return a + x;\n}\n");
+
+    assert!(error.starts_with("[crusty::error] line 3, col 16: undefined variable 'x'"), "{}", error);
+}
+
+#[test]
+fn pointer_arithmetic_moves_by_elements() {
+    let (code, stdout) = run("pointer_arithmetic", r#"
+        int length(char* s) {
+            char* end = s;
+            while (*end) {
+                end = end + 1;
+            }
+            return end - s;
+        }
+        int main() {
+            char* s = "crusty";
+            printf("%s %c\n", s + 1, *(s + 2));
+
+            int numbers[4];
+            numbers[1] = 7;
+            numbers[3] = 9;
+            int* first = &numbers[0];
+            int* last = first + 3;
+            printf("%d %d %ld\n", *(1 + first), *last, last - first);
+            return length(s);
+        }
+    "#);
+
+    assert_eq!(code, 6);
+    assert_eq!(stdout, "rusty u\n7 9 3\n");
+}
+
+#[test]
+fn array_elements_are_summed() {
+    let (code, _) = run("array_sum", r#"
+        int main() {
+            int arr[5];
+            arr[0] = 3;
+            arr[1] = 4;
+            return arr[0] + arr[1];
+        }
+    "#);
+
+    assert_eq!(code, 7);
+}
+
+#[test]
+fn array_elements_use_the_width_of_their_type() {
+    let (_, stdout) = run("array_widths", r#"
+        int main() {
+            char* names[2];
+            names[0] = "first";
+            names[1] = "second";
+            long big[2];
+            big[1] = 5000000000;
+            char* name = names[1];
+            printf("%s %s %s %ld\n", names[0], name, names[1] + 3, big[1] + 1);
+            return 0;
+        }
+    "#);
+
+    assert_eq!(stdout, "first second ond 5000000001\n");
+}
+
+#[test]
+fn bool_holds_a_comparison_result() {
+    let (code, _) = run("bool_comparison", r#"
+        int main() {
+            bool b = 3 < 5;
+            return b;
+        }
+    "#);
+
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn check_reports_type_errors_without_writing_assembly() {
+    let dir = work_dir("check_type_error");
+    std::fs::write(dir.join("main.c"), "int main() {\n    int x = \"text\";\n    return x;\n}\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crusty"))
+        .current_dir(&dir)
+        .arg("--check").arg("--color=never").arg("main.c")
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(stdout.contains("cannot initialize 'int' variable 'x' with 'char*'"), "{}", stdout);
+    assert!(!dir.join("out.s").exists());
+}
+
+#[test]
+fn main_without_return_exits_with_zero() {
+    let (code, stdout) = run("implicit_return", r#"int main() { printf("hi"); }"#);
+
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "hi");
+}
+
+#[test]
+fn crlf_and_lone_cr_line_endings_count_lines() {
+    let crlf_error = check_error("crlf_lines", "int main() {\r\n    int a = 1;\r\n\r\n    return a +;\r\n}\r\n");
+    assert!(crlf_error.contains("line 4, col 15:"), "{}", crlf_error);
+
+    let cr_error = check_error("cr_lines", "int main() {\r    -- comment\r    return a;\r}\r");
+    assert!(cr_error.contains("line 3, col 12: undefined variable 'a'"), "{}", cr_error);
+}
+
+#[test]
+fn adjacent_constant_printfs_are_merged() {
+    let source = r#"
+        int main() {
+            printf("a");
+            printf("b\n");
+            return 0;
+        }
+    "#;
+
+    let merged = assembly("tail_strings", &["--optimize-tail-strings"], source);
+    assert_eq!(merged.matches("call printf").count(), 1, "{}", merged);
+    assert!(merged.contains(".string \"ab\\n\""), "{}", merged);
+
+    let unmerged = assembly("tail_strings_off", &[], source);
+    assert_eq!(unmerged.matches("call printf").count(), 2, "{}", unmerged);
+
+    let (_, stdout) = run_with_args("tail_strings_run", &["--optimize-tail-strings"], source);
+    assert_eq!(stdout, "ab\n");
+}
+
+#[test]
+fn returns_share_one_epilogue() {
+    let source = r#"
+        int sign(int n) {
+            if (n < 0) {
+                return -1;
+            }
+            return 1;
+        }
+        int main() {
+            return sign(-4) + 1;
+        }
+    "#;
+
+    let asm = assembly("shared_epilogue", &[], source);
+    let sign = &asm[asm.find("sign:").unwrap()..asm.find("main:").unwrap()];
+    assert_eq!(sign.matches("jmp .Lreturn_sign").count(), 2, "{}", sign);
+    assert_eq!(sign.matches(".Lreturn_sign:").count(), 1, "{}", sign);
+    assert_eq!(sign.lines().filter(|line| line.trim() == "leave").count(), 1, "{}", sign);
+    assert_eq!(sign.lines().filter(|line| line.trim() == "ret").count(), 1, "{}", sign);
+
+    assert_eq!(run("shared_epilogue_run", source).0, 0);
+}
+
+#[test]
+fn att_and_intel_output_match_their_golden_files() {
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let source = std::fs::read_to_string(golden.join("syntax.c")).unwrap();
+
+    let att = assembly("golden_att", &["--syntax", "att"], &source);
+    assert_eq!(att, std::fs::read_to_string(golden.join("syntax.att.s")).unwrap());
+
+    let intel = assembly("golden_intel", &["--syntax", "intel"], &source);
+    assert_eq!(intel, std::fs::read_to_string(golden.join("syntax.intel.s")).unwrap());
+}
+
+#[test]
+fn extern_global_is_defined_in_a_linked_c_file() {
+    let library = work_dir("extern_global").join("counter.c");
+    std::fs::write(&library, "int counter = 41;\nlong total = 5000000000;\n").unwrap();
+
+    let (code, stdout) = run_with_args("extern_global", &["--link-arg", library.to_str().unwrap()], r#"
+        extern int counter;
+        extern long total;
+        int main() {
+            counter = counter + 1;
+            printf("%ld\n", total);
+            return counter;
+        }
+    "#);
+
+    assert_eq!(code, 42);
+    assert_eq!(stdout, "5000000000\n");
+}
+
+#[test]
+fn static_function_is_not_exported() {
+    let asm = assembly("static_function", &[], r#"
+        static int helper() { return 1; }
+        int visible() { return helper(); }
+        int main() { return visible(); }
+    "#);
+
+    assert!(!asm.contains(".globl helper"), "{}", asm);
+    assert!(asm.contains("helper:"), "{}", asm);
+    assert!(asm.contains(".globl visible"), "{}", asm);
+    assert!(asm.contains(".globl main"), "{}", asm);
+}
+
+#[test]
+fn c_main_calls_a_crusty_function() {
+    let c_main = work_dir("called_from_c").join("c_main.c");
+    std::fs::write(&c_main, "#include <stdio.h>\nlong triple(long n);\nint main(void) { printf(\"%ld\\n\", triple(14)); return 0; }\n").unwrap();
+
+    let (code, stdout) = run_with_args("called_from_c", &["--link-arg", c_main.to_str().unwrap()], r#"
+        long triple(long n) {
+            return n * 3;
+        }
+    "#);
+
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "42\n");
+}
+
+#[test]
+fn noreturn_function_ends_its_caller() {
+    let (code, stdout) = run("noreturn", r#"
+        @noreturn int fail(int status) {
+            printf("failing\n");
+            exit(status);
+        }
+        int check(int n) {
+            if (n > 0) {
+                return n;
+            }
+            fail(3);
+        }
+        int main() {
+            check(1);
+            check(0);
+            return 0;
+        }
+    "#);
+
+    assert_eq!(code, 3);
+    assert_eq!(stdout, "failing\n");
+}
+
+#[test]
+fn code_after_a_noreturn_call_is_pruned() {
+    let (asm, stdout) = assembly_and_output("noreturn_pruning", &[], r#"
+        @noreturn int die(int status) { exit(status); }
+        int main() {
+            die(2);
+            printf("never printed\n");
+            return 0;
+        }
+    "#);
+
+    assert!(stdout.contains("warning: 2 unreachable statement(s) after the call to 'die' in 'main' removed"), "{}", stdout);
+    assert!(!asm.contains("never printed"), "{}", asm);
+}
+
+#[test]
+fn printed_frame_sizes_match_the_prologue() {
+    let (asm, stdout) = assembly_and_output("frame_sizes", &["--print-frame-sizes"], r#"
+        int locals() {
+            long a = 1;
+            int b = 2;
+            char c = 'c';
+            return b;
+        }
+        int main() {
+            return locals();
+        }
+    "#);
+
+    for (name, size) in [("locals", 32), ("main", 0)] {
+        assert!(stdout.contains(&format!("frame size of '{}': {} bytes", name, size)), "{}", stdout);
+    }
+
+    let locals = &asm[asm.find("locals:").unwrap()..asm.find("main:").unwrap()];
+    assert!(locals.contains("subq $32, %rsp"), "{}", locals);
+    let main = &asm[asm.find("main:").unwrap()..];
+    assert!(!main.contains("subq"), "{}", main);
+}
+
+#[test]
+fn color_flag_overrides_terminal_detection() {
+    let input = work_dir("color").join("main.c");
+    std::fs::write(&input, "int main() { return 0; }\n").unwrap();
+
+    let banner = |color: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_crusty"))
+            .arg("--check").arg(color).arg(&input)
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    /* stdout is a pipe here, so auto means no color */
+    assert!(banner("--color=always").contains("\x1b["));
+    assert!(!banner("--color=never").contains("\x1b["));
+    assert!(!banner("--color=auto").contains("\x1b["));
+    assert_eq!(banner("--color=never"), "[crusty] No errors found.\n");
+}
+
+#[test]
+fn file_macro_joins_adjacent_string_literals() {
+    let (_, stdout) = run("file_concatenation", r#"
+        int main() {
+            printf("in " __FILE__ ": error\n");
+            return 0;
+        }
+    "#);
+
+    let input = work_dir("file_concatenation").join("main.c");
+    assert_eq!(stdout, format!("in {}: error\n", input.display()));
+}
+
+#[test]
+fn logical_operators_short_circuit() {
+    let (code, stdout) = run("logical_operators", r#"
+        int touch(int value) {
+            printf("touched\n");
+            return value;
+        }
+        int main() {
+            int r = (1 < 2) && (3 > 10);
+            int a = 0 && touch(1);
+            int o = 1 || touch(0);
+            int both = touch(2) && touch(3);
+            return r * 1000 + a * 100 + o * 10 + both;
+        }
+    "#);
+
+    assert_eq!(code, 11);
+    assert_eq!(stdout, "touched\ntouched\n");
+}
+
+#[test]
+fn modulo_yields_the_remainder() {
+    let (code, _) = run("modulo", r#"
+        int main() {
+            int r = 17 % 5;
+            int n = 17;
+            int d = 5;
+            return r * 10 + n % d;
+        }
+    "#);
+
+    assert_eq!(code, 22);
+
+    let error = compile_error("modulo_by_zero", "int main() {\n    return 17 % 0;\n}\n");
+    assert!(error.contains("line 2: division by zero in 'main'"), "{}", error);
+}
+
+#[test]
+fn inner_block_variable_shadows_the_outer_one() {
+    let (code, stdout) = run("shadowing", r#"
+        int main() {
+            int x = 1;
+            if (x == 1) {
+                int x = 2;
+                printf("inner %d\n", x);
+            }
+            printf("outer %d\n", x);
+            return x;
+        }
+    "#);
+
+    assert_eq!(code, 1);
+    assert_eq!(stdout, "inner 2\nouter 1\n");
+}
+
+#[test]
+fn o1_folds_constant_arithmetic() {
+    let source = r#"
+        int main() {
+            int x = 2 * 3 + 4;
+            return x;
+        }
+    "#;
+
+    let folded = assembly("folding", &["-O1"], source);
+    assert!(folded.contains("movl $10, "), "{}", folded);
+    assert!(!folded.contains("imul"), "{}", folded);
+
+    let unfolded = assembly("folding_off", &[], source);
+    assert!(unfolded.contains("imul"), "{}", unfolded);
+
+    assert_eq!(run_with_args("folding_run", &["-O1"], source).0, 10);
+
+    let error = compile_error_with_args("folding_zero", &["-O1"], "int main() {\n    int x = 1;\n    return 4 / (2 - 2);\n}\n");
+    assert!(error.starts_with("[crusty::error] line 3: division by zero in constant expression"), "{}", error);
+}
+
+#[test]
+fn char_pointer_return_value_is_stored_whole() {
+    let (code, stdout) = run("char_pointer_return", r#"
+        char* get_name() {
+            return "crusty";
+        }
+        char* pass_on() {
+            char* name = get_name();
+            return name;
+        }
+        int main() {
+            char* s = pass_on();
+            printf("%s\n", s);
+            printf("%s\n", get_name());
+            return 0;
+        }
+    "#);
+
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "crusty\ncrusty\n");
+}
+
+#[test]
+fn compound_assignment_updates_the_variable() {
+    let (code, stdout) = run("compound_assignment", r#"
+        int main() {
+            int i = 0;
+            int sum = 0;
+            while (i < 5) {
+                sum += i;
+                i += 1;
+            }
+            char c = 'a';
+            c += 2;
+            int x = 20;
+            x -= 4;
+            x *= 3;
+            x /= 2;
+            printf("%d %c %d\n", sum, c, x);
+            return i;
+        }
+    "#);
+
+    assert_eq!(code, 5);
+    assert_eq!(stdout, "10 c 24\n");
+}
+
+#[test]
+fn long_multiplication_keeps_64_bits() {
+    let (code, stdout) = run("long_multiply", r#"
+        long product(long a, long b) {
+            return a * b;
+        }
+        int main() {
+            long a = 100000;
+            long b = 300000;
+            long c = a * b;
+            printf("%ld %ld\n", c, product(70000, 70000));
+            return c / 1000000000;
+        }
+    "#);
+
+    assert_eq!(stdout, "30000000000 4900000000\n");
+    assert_eq!(code, 30);
+}
+
+#[test]
+fn redeclaration_is_an_error_only_in_the_same_scope() {
+    let error = compile_error("redeclaration", "int main() {\n    int x = 1;\n    int x = 2;\n    return x;\n}\n");
+    assert!(error.contains("variable 'x' already declared in this scope at line 3"), "{}", error);
+
+    let (code, _) = run("redeclaration_shadowing", r#"
+        int main() {
+            int x = 1;
+            while (x < 3) {
+                int x = 10;
+                return x;
+            }
+            return x;
+        }
+    "#);
+    assert_eq!(code, 10);
+}
+
+#[test]
+fn hex_binary_and_octal_literals() {
+    let (code, stdout) = run("radix_literals", r#"
+        int main() {
+            int x = 0xFF;
+            printf("%d %d %d\n", x, 0b1010, 0o17);
+            return x;
+        }
+    "#);
+
+    assert_eq!(code, 255);
+    assert_eq!(stdout, "255 10 15\n");
+
+    let error = check_error("bad_binary_digit", "int main() {\n    return 0b2;\n}\n");
+    assert!(error.contains("line 2, col 14: invalid digit '2' in binary literal"), "{}", error);
+}
+
+#[test]
+fn integer_literals_round_trip_exactly() {
+    /* 2^53 + 1 has no exact f64, it only survives as an integer */
+    let source = r#"
+        int main() {
+            long x = 3000000000;
+            long big = 9007199254740993;
+            double d = 2.0;
+            printf("%ld %ld %f\n", x, big, d);
+            return 0;
+        }
+    "#;
+
+    let input = work_dir("literal_ast").join("main.c");
+    std::fs::write(&input, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crusty")).arg("--emit-ast").arg(&input).output().unwrap();
+    let ast = String::from_utf8_lossy(&output.stdout);
+    for node in ["Int 3000000000", "Int 9007199254740993", "Float 2.0"] {
+        assert!(ast.contains(node), "{}", ast);
+    }
+
+    let (_, stdout) = run("literal_run", source);
+    assert_eq!(stdout, "3000000000 9007199254740993 2.000000\n");
+}
+
+#[test]
+fn double_arithmetic_is_printed() {
+    let (code, stdout) = run("double_arithmetic", r#"
+        int main() {
+            double a = 3.5;
+            printf("%.2f\n", a + 1.25);
+            printf("%f %f %f\n", a - 1.0, a * 2.0, a / 2.0);
+            return 0;
+        }
+    "#);
+
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "4.75\n2.500000 7.000000 1.750000\n");
+}
+
+#[test]
+fn call_with_the_wrong_number_of_arguments_is_rejected() {
+    let error = check_error("arity_mismatch", r#"
+        int foo(int a, int b) { return a + b; }
+        int main() { return foo(1, 2, 3); }
+    "#);
+    assert!(error.contains("function 'foo' expects 2 arguments but got 3"), "{}", error);
+
+    let (code, _) = run("variadic_library_call", r#"
+        int main() {
+            printf("%d %d %d\n", 1, 2, 3);
+            return 0;
+        }
+    "#);
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn store_through_a_pointer_round_trips() {
+    let (code, stdout) = run("pointer_round_trip", r#"
+        int set(int* out, int value) {
+            *out = value;
+            return 0;
+        }
+        int main() {
+            int x = 5;
+            int* p = &x;
+            *p = 42;
+            printf("%d %d\n", x, *p);
+
+            set(p, x * 2);
+            char c = 'a';
+            char* q = &c;
+            *q = 'z';
+            return x - 84 + (*q == 'z');
+        }
+    "#);
+
+    assert_eq!(stdout, "42 42\n");
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn one_statement_declares_several_variables() {
+    let (code, _) = run("multi_declaration", r#"
+        int main() {
+            int a = 1, b = 2, c = 3;
+            return a + b + c;
+        }
+    "#);
+
+    assert_eq!(code, 6);
+}
+
+#[test]
+fn stray_character_is_reported_where_it_is() {
+    let error = check_error("stray_character", "int main() {\n    int x = 1;\n    x = x $ 2;\n    return x;\n}\n");
+
+    assert!(error.starts_with("[crusty::error] line 3, col 11: unknown character '$'"), "{}", error);
+}